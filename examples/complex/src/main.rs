@@ -90,7 +90,7 @@ fn main() -> TimerResult<()> {
             println!(
                 "{}\tbefore\t{} unit ({} micros), span:{}",
                 i,
-                dis / 1000 as u128,
+                dis / 1000_u128,
                 dis,
                 span
             );
@@ -0,0 +1,61 @@
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+use xpd_timer::time_wheel;
+
+const PRODUCERS: usize = 8;
+const ARRANGEMENTS_PER_PRODUCER: usize = 200_000;
+const INTERVAL: Duration = Duration::from_millis(1);
+
+/// Measure the arrangement throughput with many producers.
+///
+/// `cargo run --release --example contention`
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    println!(
+        "{} cores, tick interval {:?}, {} producers x {} arrangements",
+        cores, INTERVAL, PRODUCERS, ARRANGEMENTS_PER_PRODUCER
+    );
+
+    let (scheduler, receiver) = time_wheel::<usize>(INTERVAL);
+    let scheduler = Arc::new(scheduler);
+    let barrier = Arc::new(Barrier::new(PRODUCERS + 1));
+
+    let producers = (0..PRODUCERS)
+        .map(|producer| {
+            let scheduler = scheduler.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..ARRANGEMENTS_PER_PRODUCER {
+                    let delay = Duration::from_millis(1000 + (i % 1000) as u64);
+                    scheduler.arrange(producer).after(delay);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    let start = Instant::now();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let total = PRODUCERS * ARRANGEMENTS_PER_PRODUCER;
+    println!(
+        "{} producers arranged {} tasks in {:?}, {:.0} arrangements/s",
+        PRODUCERS,
+        total,
+        elapsed,
+        total as f64 / elapsed.as_secs_f64()
+    );
+
+    for _ in 0..total {
+        receiver.recv().unwrap();
+    }
+    println!("all tasks received after {:?}", start.elapsed());
+}
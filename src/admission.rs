@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{TimerError, TimerResult};
+
+/// Snapshot of the admission control state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdmissionMetrics {
+    /// Tasks admitted and not expired or cancelled yet, including the ones still on the way to the timer thread.
+    pub pending: usize,
+    /// Tasks rejected because a limit is exceeded.
+    pub rejected: usize,
+}
+
+/// Limits of the pending tasks, checked before a task is sent to the timer thread.
+///
+/// Tasks are counted from the moment they are admitted until they expire or are cancelled,
+/// so the inbox of the timer thread is bounded too.
+pub(crate) struct Admission {
+    max_pending: Option<usize>,
+    /// Limit and count of the limited tags.
+    tags: Mutex<HashMap<String, (usize, usize)>>,
+    /// Wait this long for room, reject right away if `None`.
+    block: Option<Duration>,
+    pending: AtomicUsize,
+    rejected: AtomicUsize,
+    lock: Mutex<()>,
+    released: Condvar,
+}
+
+impl Admission {
+    pub(crate) fn new(
+        max_pending: Option<usize>,
+        tag_limits: HashMap<String, usize>,
+        block: Option<Duration>,
+    ) -> Self {
+        let tags = tag_limits
+            .into_iter()
+            .map(|(tag, limit)| (tag, (limit, 0)))
+            .collect();
+        Admission {
+            max_pending,
+            tags: Mutex::new(tags),
+            block,
+            pending: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Admit `count` tasks with `tags`, waiting for room if configured so.
+    pub(crate) fn acquire(&self, tags: &[String], count: usize) -> TimerResult<()> {
+        let mut result = self.try_acquire(tags, count);
+
+        if let (Err(_), Some(block)) = (&result, self.block) {
+            let deadline = Instant::now() + block;
+            let mut guard = self.lock.lock().unwrap();
+            loop {
+                // checked again under the lock, a release in between is not missed
+                result = self.try_acquire(tags, count);
+                let now = Instant::now();
+                if result.is_ok() || now >= deadline {
+                    break;
+                }
+                guard = self.released.wait_timeout(guard, deadline - now).unwrap().0;
+            }
+        }
+
+        if result.is_err() {
+            self.rejected.fetch_add(count, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Admit `count` tasks with `tags` if there is room, never waiting for it.
+    pub(crate) fn try_acquire(&self, tags: &[String], count: usize) -> TimerResult<()> {
+        let max_pending = self.max_pending.unwrap_or(usize::MAX);
+        self.pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |pending| {
+                pending.checked_add(count).filter(|&sum| sum <= max_pending)
+            })
+            .map_err(|_| {
+                TimerError::CapacityExceeded(format!("max pending {} reached", max_pending))
+            })?;
+
+        let mut limited = self.tags.lock().unwrap();
+        let full = tags.iter().find(|tag| {
+            limited
+                .get(*tag)
+                .is_some_and(|(limit, pending)| pending + count > *limit)
+        });
+        if let Some(tag) = full {
+            let message = format!("limit of tag {:?} reached", tag);
+            drop(limited);
+            self.pending.fetch_sub(count, Ordering::AcqRel);
+            return Err(TimerError::CapacityExceeded(message));
+        }
+        for tag in tags {
+            if let Some((_, pending)) = limited.get_mut(tag) {
+                *pending += count;
+            }
+        }
+        Ok(())
+    }
+
+    /// A task with `tags` expired or was cancelled.
+    pub(crate) fn release(&self, tags: &[String]) {
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        {
+            let mut limited = self.tags.lock().unwrap();
+            for tag in tags {
+                if let Some((_, pending)) = limited.get_mut(tag) {
+                    *pending -= 1;
+                }
+            }
+        }
+
+        if self.block.is_some() {
+            let _guard = self.lock.lock().unwrap();
+            self.released.notify_all();
+        }
+    }
+
+    /// Hand the room of a pending task with `replaced` tags over to the task with `tags` replacing it,
+    /// `false` if a limit of the tags it does not share with the replaced one is reached.
+    pub(crate) fn replace(&self, replaced: &[String], tags: &[String]) -> bool {
+        {
+            let mut limited = self.tags.lock().unwrap();
+            let full = tags
+                .iter()
+                .filter(|tag| !replaced.contains(tag))
+                .any(|tag| {
+                    limited
+                        .get(tag)
+                        .is_some_and(|(limit, pending)| pending + 1 > *limit)
+                });
+            if full {
+                return false;
+            }
+            for tag in replaced {
+                if let Some((_, pending)) = limited.get_mut(tag) {
+                    *pending -= 1;
+                }
+            }
+            for tag in tags {
+                if let Some((_, pending)) = limited.get_mut(tag) {
+                    *pending += 1;
+                }
+            }
+        }
+
+        if self.block.is_some() {
+            let _guard = self.lock.lock().unwrap();
+            self.released.notify_all();
+        }
+        true
+    }
+
+    pub(crate) fn metrics(&self) -> AdmissionMetrics {
+        AdmissionMetrics {
+            pending: self.pending.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = HashMap::from([("tenant".to_string(), 2)]);
+        let admission = Admission::new(Some(3), limits, None);
+
+        assert!(admission.acquire(&tags(&["tenant"]), 1).is_ok());
+        assert!(admission.acquire(&tags(&["tenant", "other"]), 1).is_ok());
+        assert!(matches!(
+            admission.acquire(&tags(&["tenant"]), 1),
+            Err(TimerError::CapacityExceeded(_))
+        ));
+        assert!(admission.acquire(&[], 1).is_ok());
+        assert!(admission.acquire(&[], 1).is_err());
+        assert_eq!(
+            admission.metrics(),
+            AdmissionMetrics {
+                pending: 3,
+                rejected: 2
+            }
+        );
+
+        admission.release(&tags(&["tenant"]));
+        assert!(admission.acquire(&tags(&["tenant"]), 1).is_ok());
+        assert_eq!(admission.metrics().pending, 3);
+
+        // the room of a replaced task is handed over, within the limits of the other tags
+        assert!(admission.replace(&tags(&["tenant"]), &tags(&["tenant"])));
+        assert!(!admission.replace(&[], &tags(&["tenant"])));
+        assert!(admission.replace(&tags(&["tenant"]), &tags(&["other"])));
+        assert!(admission.replace(&[], &tags(&["tenant"])));
+        assert_eq!(admission.metrics().pending, 3);
+    }
+
+    #[test]
+    fn test_block() {
+        let admission = Arc::new(Admission::new(
+            Some(1),
+            HashMap::new(),
+            Some(Duration::from_millis(500)),
+        ));
+        admission.acquire(&[], 1).unwrap();
+
+        let releaser = {
+            let admission = admission.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                admission.release(&[]);
+            })
+        };
+        assert!(admission.acquire(&[], 1).is_ok());
+        releaser.join().unwrap();
+
+        let admission = Admission::new(Some(1), HashMap::new(), Some(Duration::from_millis(10)));
+        admission.acquire(&[], 1).unwrap();
+        assert!(admission.acquire(&[], 1).is_err());
+        assert_eq!(admission.metrics().rejected, 1);
+    }
+}
@@ -1,5 +1,17 @@
+mod backoff;
+mod deadline;
 mod error;
+mod id;
+mod jitter;
 mod result;
+mod rounding;
+mod rrule;
 
+pub use backoff::*;
+pub use deadline::*;
 pub use error::*;
+pub use id::*;
+pub use jitter::*;
 pub use result::*;
+pub use rounding::*;
+pub use rrule::*;
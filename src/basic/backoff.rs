@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Delay before retrying a failed operation, from the number of failed attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// The same delay for every attempt.
+    Constant(Duration),
+    /// `initial`, growing by `step` per attempt, up to `max`.
+    Linear {
+        initial: Duration,
+        step: Duration,
+        max: Duration,
+    },
+    /// `initial`, multiplied by `factor` per attempt, up to `max`.
+    Exponential {
+        initial: Duration,
+        factor: f64,
+        max: Duration,
+    },
+    /// Random between `base` and 3 times the previous delay, up to `max`,
+    /// so retries of many clients spread out instead of retrying in lockstep.
+    DecorrelatedJitter { base: Duration, max: Duration },
+}
+
+impl Backoff {
+    /// Exponential backoff doubling from `initial` up to `max`.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Backoff::Exponential {
+            initial,
+            factor: 2.0,
+            max,
+        }
+    }
+
+    /// Delay after the failed `attempt`, from 1, `previous` is the delay before it.
+    pub(crate) fn delay(
+        &self,
+        attempt: u32,
+        previous: Option<Duration>,
+        rng: &mut impl Rng,
+    ) -> Duration {
+        let attempt = attempt.max(1);
+        match *self {
+            Backoff::Constant(delay) => delay,
+            Backoff::Linear { initial, step, max } => step
+                .checked_mul(attempt - 1)
+                .and_then(|grown| initial.checked_add(grown))
+                .map_or(max, |delay| delay.min(max)),
+            Backoff::Exponential {
+                initial,
+                factor,
+                max,
+            } => {
+                // saturates to infinity on a large attempt, capped by `max`
+                let delay = initial.as_secs_f64() * factor.powf(f64::from(attempt - 1));
+                Duration::try_from_secs_f64(delay).map_or(max, |delay| delay.min(max))
+            }
+            Backoff::DecorrelatedJitter { base, max } => {
+                let upper = previous.unwrap_or(base).saturating_mul(3).max(base);
+                let delay = base + (upper - base).mul_f64(rng.gen::<f64>());
+                delay.min(max)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_delay() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let ms = Duration::from_millis;
+
+        let constant = Backoff::Constant(ms(50));
+        assert_eq!(constant.delay(9, Some(ms(50)), &mut rng), ms(50));
+
+        let linear = Backoff::Linear {
+            initial: ms(100),
+            step: ms(50),
+            max: ms(220),
+        };
+        let delays = (1..=4).map(|attempt| linear.delay(attempt, None, &mut rng));
+        assert_eq!(
+            delays.collect::<Vec<_>>(),
+            [ms(100), ms(150), ms(200), ms(220)]
+        );
+
+        let exponential = Backoff::exponential(ms(100), ms(1000));
+        let delays = (1..=5).map(|attempt| exponential.delay(attempt, None, &mut rng));
+        assert_eq!(
+            delays.collect::<Vec<_>>(),
+            [ms(100), ms(200), ms(400), ms(800), ms(1000)]
+        );
+        assert_eq!(exponential.delay(u32::MAX, None, &mut rng), ms(1000));
+
+        let jitter = Backoff::DecorrelatedJitter {
+            base: ms(100),
+            max: ms(2000),
+        };
+        let mut previous = None;
+        for attempt in 1..=20 {
+            let delay = jitter.delay(attempt, previous, &mut rng);
+            let upper = previous.unwrap_or(ms(100)) * 3;
+            assert!(delay >= ms(100) && delay <= upper.min(ms(2000)));
+            previous = Some(delay);
+        }
+    }
+}
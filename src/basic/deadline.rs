@@ -0,0 +1,32 @@
+use std::time::{Duration, SystemTime};
+
+/// When a task expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deadline {
+    /// At a specific time.
+    At(SystemTime),
+    /// After a specific duration from now.
+    After(Duration),
+}
+
+impl Deadline {
+    /// The time the deadline is at.
+    pub fn when(&self) -> SystemTime {
+        match *self {
+            Deadline::At(when) => when,
+            Deadline::After(after) => SystemTime::now() + after,
+        }
+    }
+}
+
+impl From<SystemTime> for Deadline {
+    fn from(when: SystemTime) -> Self {
+        Deadline::At(when)
+    }
+}
+
+impl From<Duration> for Deadline {
+    fn from(after: Duration) -> Self {
+        Deadline::After(after)
+    }
+}
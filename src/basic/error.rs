@@ -4,6 +4,12 @@ use std::fmt;
 pub enum TimerError {
     RecvError(String),
     SendError(String),
+    /// A limit of the pending tasks is exceeded.
+    CapacityExceeded(String),
+    /// The deadline of a `Timeout` elapsed before its future completed.
+    Elapsed,
+    /// A recurrence rule can not be parsed, or it has no occurrence left.
+    InvalidRule(String),
 }
 
 impl std::error::Error for TimerError {}
@@ -13,6 +19,9 @@ impl fmt::Display for TimerError {
         match self {
             TimerError::RecvError(msg) => write!(f, "Internal Error:{:?}", msg),
             TimerError::SendError(msg) => write!(f, "Internal Error:{:?}", msg),
+            TimerError::CapacityExceeded(msg) => write!(f, "Capacity Exceeded:{:?}", msg),
+            TimerError::Elapsed => write!(f, "Deadline Elapsed"),
+            TimerError::InvalidRule(msg) => write!(f, "Invalid Rule:{:?}", msg),
         }
     }
 }
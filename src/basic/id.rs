@@ -0,0 +1,51 @@
+/// Number of bits of `TimerId` used for the shard.
+const SHARD_BITS: u32 = 8;
+
+const SEQUENCE_BITS: u32 = u64::BITS - SHARD_BITS;
+
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// Maximum number of shards.
+pub(crate) const MAX_SHARDS: usize = 1 << SHARD_BITS;
+
+/// Identifies an arranged task, for cancelling it.
+///
+/// The shard the task is arranged to is encoded in the high bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+impl TimerId {
+    pub(crate) fn new(shard: usize, sequence: u64) -> Self {
+        debug_assert!(shard < MAX_SHARDS, "shard is overflow");
+        TimerId(((shard as u64) << SEQUENCE_BITS) | (sequence & SEQUENCE_MASK))
+    }
+
+    /// Index of the shard the task is arranged to.
+    pub fn shard(&self) -> usize {
+        (self.0 >> SEQUENCE_BITS) as usize
+    }
+
+    pub(crate) fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub(crate) const fn from_u64(id: u64) -> Self {
+        TimerId(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard() {
+        let id = TimerId::new(3, 42);
+        assert_eq!(id.shard(), 3);
+        assert_eq!(id.as_u64() & SEQUENCE_MASK, 42);
+
+        let id = TimerId::new(MAX_SHARDS - 1, SEQUENCE_MASK);
+        assert_eq!(id.shard(), MAX_SHARDS - 1);
+        assert_eq!(id.as_u64(), u64::MAX);
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Random delay added to a deadline, so tasks arranged with the same delay do not expire in one tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jitter {
+    /// Up to the duration, uniformly distributed.
+    Uniform(Duration),
+    /// Exponentially distributed with the duration as the mean, most are short with a long tail.
+    Exponential(Duration),
+}
+
+impl Jitter {
+    /// Draw a delay from the distribution.
+    pub(crate) fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match *self {
+            Jitter::Uniform(max) => max.mul_f64(rng.gen::<f64>()),
+            // inverse transform sampling, `1 - u` is in (0, 1]
+            Jitter::Exponential(mean) => mean.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_sample() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let jitter = Duration::from_millis(100);
+
+        let uniform = (0..1000)
+            .map(|_| Jitter::Uniform(jitter).sample(&mut rng))
+            .collect::<Vec<_>>();
+        assert!(uniform.iter().all(|delay| *delay <= jitter));
+        let mean = uniform.iter().sum::<Duration>() / 1000;
+        assert!(mean > Duration::from_millis(40) && mean < Duration::from_millis(60));
+
+        let exponential = (0..1000)
+            .map(|_| Jitter::Exponential(jitter).sample(&mut rng))
+            .collect::<Vec<_>>();
+        let mean = exponential.iter().sum::<Duration>() / 1000;
+        assert!(mean > Duration::from_millis(80) && mean < Duration::from_millis(120));
+
+        let mut again = StdRng::seed_from_u64(7);
+        assert_eq!(Jitter::Uniform(jitter).sample(&mut again), uniform[0]);
+    }
+}
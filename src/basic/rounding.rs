@@ -0,0 +1,13 @@
+/// How a deadline between two ticks is rounded to a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Round up, a task is never delivered before its deadline.
+    ///
+    /// The deadline is checked again on delivery, a task is held back if the clock is behind it.
+    #[default]
+    NeverEarly,
+    /// Round to the nearest tick, a task is delivered within half a tick of its deadline.
+    Nearest,
+    /// Round down, a task is delivered no later than the tick its deadline is in.
+    NeverLate,
+}
@@ -0,0 +1,985 @@
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{TimerError, TimerResult};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// The last day an occurrence can fall on, 9999-12-31.
+const MAX_DAY: i64 = 2_932_896;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Yearly,
+    Monthly,
+    Weekly,
+    Daily,
+    Hourly,
+    Minutely,
+}
+
+/// A weekday of BYDAY, the `ordinal`th of the month or the year, negative from the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByDay {
+    ordinal: Option<i32>,
+    /// From 0 for Monday.
+    weekday: i64,
+}
+
+/// A recurrence rule of RFC 5545, e.g. `FREQ=MONTHLY;BYDAY=-1FR;BYHOUR=17;BYMINUTE=0`.
+///
+/// It supports FREQ from YEARLY to MINUTELY, INTERVAL, COUNT, UNTIL, BYDAY with ordinals,
+/// BYMONTHDAY, BYHOUR, BYMINUTE and BYSETPOS, with weeks starting on Monday.
+/// The parsed text may also have DTSTART and EXDATE lines. Times are in UTC with a `Z`,
+/// or at a fixed offset like `+0200` instead of a time zone. The local times of the rule
+/// are at the offset of DTSTART, UTC if it has none.
+/// Without DTSTART the rule starts at the time it is parsed.
+///
+/// DTSTART is an occurrence only if it matches the rule, and an excluded date counts toward COUNT.
+/// An EXDATE of a date only, like `20261225`, excludes the occurrences of that local day.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::RRule;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// // every 2nd Tuesday at 09:30 in UTC+2, 10 times
+/// let rule = "DTSTART:20261020T093000+0200\n\
+///             RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU;COUNT=10"
+///     .parse::<RRule>()
+///     .unwrap();
+///
+/// let occurrences = rule.occurrences().collect::<Vec<_>>();
+/// assert_eq!(occurrences.len(), 10);
+/// // 2026-10-20T07:30:00Z
+/// assert_eq!(occurrences[0], UNIX_EPOCH + Duration::from_secs(1_792_481_400));
+/// assert_eq!(occurrences[1] - Duration::from_secs(14 * 86_400), occurrences[0]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    /// Seconds since the epoch in UTC, as are `until` and `exdates`.
+    dtstart: i64,
+    /// Seconds east of UTC of the local times.
+    offset: i64,
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<i64>,
+    by_day: Vec<ByDay>,
+    by_month_day: Vec<i32>,
+    by_hour: Vec<u32>,
+    by_minute: Vec<u32>,
+    by_set_pos: Vec<i32>,
+    exdates: Vec<i64>,
+    /// Local days since the epoch of the date-only EXDATEs, excluding the whole day.
+    exdays: Vec<i64>,
+}
+
+impl RRule {
+    /// Start the rule at `dtstart`, truncated to the second.
+    pub fn dtstart(mut self, dtstart: SystemTime) -> Self {
+        self.dtstart = seconds_of(dtstart);
+        self
+    }
+
+    /// Evaluate the local times of the rule at `seconds` east of UTC, e.g. `3600` for UTC+1.
+    ///
+    /// The instants of DTSTART, UNTIL and the excluded date-times are kept.
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.offset = i64::from(seconds);
+        self
+    }
+
+    /// Exclude an occurrence, like an EXDATE.
+    pub fn exdate(mut self, when: SystemTime) -> Self {
+        self.exdates.push(seconds_of(when));
+        self
+    }
+
+    /// The occurrences in order, from DTSTART.
+    pub fn occurrences(&self) -> Occurrences {
+        Occurrences {
+            rule: self.clone(),
+            from: self.dtstart,
+            period: 0,
+            pending: VecDeque::new(),
+            counted: 0,
+            done: false,
+        }
+    }
+
+    /// The occurrences in order, from `when` on.
+    ///
+    /// It starts right at the period of `when`, unless the rule has a COUNT,
+    /// which is counted from DTSTART, so the earlier occurrences are walked through.
+    pub fn occurrences_from(&self, when: SystemTime) -> Occurrences {
+        let from = seconds_of(when);
+        let period = match self.count {
+            Some(_) => 0,
+            None => self.period_of(from),
+        };
+        Occurrences {
+            from: from.max(self.dtstart),
+            period,
+            ..self.occurrences()
+        }
+    }
+
+    /// The first occurrence after `when`.
+    pub fn after(&self, when: SystemTime) -> Option<SystemTime> {
+        self.occurrences_from(when)
+            .find(|occurrence| *occurrence > when)
+    }
+
+    /// The index of the period `when` is in, or the last one before it, 0 before DTSTART.
+    fn period_of(&self, when: i64) -> i64 {
+        let start_day = (self.dtstart + self.offset).div_euclid(SECS_PER_DAY);
+        let day = (when + self.offset).div_euclid(SECS_PER_DAY);
+        let interval = i64::from(self.interval);
+        let months = |day| {
+            let (year, month, _) = civil_from_days(day);
+            year * 12 + i64::from(month - 1)
+        };
+
+        let period = match self.frequency {
+            Frequency::Yearly => (civil_from_days(day).0 - civil_from_days(start_day).0) / interval,
+            Frequency::Monthly => (months(day) - months(start_day)) / interval,
+            Frequency::Weekly => {
+                let weeks = (day - weekday(day) - (start_day - weekday(start_day))) / 7;
+                weeks / interval
+            }
+            Frequency::Daily => (day - start_day) / interval,
+            Frequency::Hourly | Frequency::Minutely => day - start_day,
+        };
+        period.max(0)
+    }
+
+    /// Periods in a row without an occurrence before giving up, a whole 400 year cycle of the calendar.
+    fn max_empty_periods(&self) -> u32 {
+        match self.frequency {
+            Frequency::Yearly => 400,
+            Frequency::Monthly => 4_800,
+            Frequency::Weekly => 20_871,
+            Frequency::Daily | Frequency::Hourly | Frequency::Minutely => 146_097,
+        }
+    }
+
+    /// The local times of the `period`th period from DTSTART, sorted, `None` past `MAX_DAY`.
+    ///
+    /// A period of HOURLY and MINUTELY is a day, the hours or minutes of it are expanded at once.
+    fn expand(&self, period: i64) -> Option<Vec<i64>> {
+        let start = self.dtstart + self.offset;
+        let start_day = start.div_euclid(SECS_PER_DAY);
+        let interval = i64::from(self.interval);
+
+        let days = match self.frequency {
+            Frequency::Yearly => {
+                let (year, _, _) = civil_from_days(start_day);
+                let year = year + period * interval;
+                if year > 9999 {
+                    return None;
+                }
+                self.year_days(year)
+            }
+            Frequency::Monthly => {
+                let (year, month, _) = civil_from_days(start_day);
+                let months = year * 12 + i64::from(month - 1) + period * interval;
+                if months / 12 > 9999 {
+                    return None;
+                }
+                self.month_days(months.div_euclid(12), months.rem_euclid(12) as u32 + 1)
+            }
+            Frequency::Weekly => {
+                let monday = start_day - weekday(start_day) + period * interval * 7;
+                if monday > MAX_DAY {
+                    return None;
+                }
+                (monday..monday + 7)
+                    .filter(|&day| match self.by_day.is_empty() {
+                        true => weekday(day) == weekday(start_day),
+                        false => self.matches_weekday(day),
+                    })
+                    .collect()
+            }
+            Frequency::Daily | Frequency::Hourly | Frequency::Minutely => {
+                let step = match self.frequency {
+                    Frequency::Daily => interval,
+                    _ => 1,
+                };
+                let day = start_day + period * step;
+                if day > MAX_DAY {
+                    return None;
+                }
+                [day]
+                    .into_iter()
+                    .filter(|&day| self.by_day.is_empty() || self.matches_weekday(day))
+                    .filter(|&day| self.matches_month_day(day))
+                    .collect()
+            }
+        };
+
+        let mut times = days
+            .into_iter()
+            .filter(|&day| day <= MAX_DAY)
+            .flat_map(|day| {
+                self.day_times(day)
+                    .into_iter()
+                    .map(move |secs| day * SECS_PER_DAY + secs)
+            })
+            .collect::<Vec<_>>();
+        times.sort_unstable();
+        times.dedup();
+
+        if self.by_set_pos.is_empty() {
+            return Some(times);
+        }
+        let len = times.len() as i64;
+        let mut selected = self
+            .by_set_pos
+            .iter()
+            .filter_map(|&pos| {
+                let index = if pos > 0 {
+                    pos as i64 - 1
+                } else {
+                    len + pos as i64
+                };
+                (0..len).contains(&index).then(|| times[index as usize])
+            })
+            .collect::<Vec<_>>();
+        selected.sort_unstable();
+        selected.dedup();
+        Some(selected)
+    }
+
+    /// The candidate days of a YEARLY period.
+    fn year_days(&self, year: i64) -> Vec<i64> {
+        let first = days_from_civil(year, 1, 1);
+        let len = if is_leap(year) { 366 } else { 365 };
+
+        if !self.by_month_day.is_empty() {
+            return (1..=12)
+                .flat_map(|month| self.month_days_of(year, month))
+                .filter(|&day| self.by_day.is_empty() || self.matches_by_day(day, first, len))
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            return self.by_day_days(first, len);
+        }
+        let (_, month, day) =
+            civil_from_days((self.dtstart + self.offset).div_euclid(SECS_PER_DAY));
+        match day <= days_in_month(year, month) {
+            true => vec![days_from_civil(year, month, day)],
+            false => Vec::new(),
+        }
+    }
+
+    /// The candidate days of a MONTHLY period.
+    fn month_days(&self, year: i64, month: u32) -> Vec<i64> {
+        let first = days_from_civil(year, month, 1);
+        let len = i64::from(days_in_month(year, month));
+
+        if !self.by_month_day.is_empty() {
+            return self
+                .month_days_of(year, month)
+                .filter(|&day| self.by_day.is_empty() || self.matches_by_day(day, first, len))
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            return self.by_day_days(first, len);
+        }
+        let (_, _, day) = civil_from_days((self.dtstart + self.offset).div_euclid(SECS_PER_DAY));
+        match day <= days_in_month(year, month) {
+            true => vec![first + i64::from(day) - 1],
+            false => Vec::new(),
+        }
+    }
+
+    /// The days of BYMONTHDAY in a month.
+    fn month_days_of(&self, year: i64, month: u32) -> impl Iterator<Item = i64> + '_ {
+        let first = days_from_civil(year, month, 1);
+        let len = i64::from(days_in_month(year, month));
+        self.by_month_day.iter().filter_map(move |&month_day| {
+            let day = match month_day > 0 {
+                true => i64::from(month_day),
+                false => len + 1 + i64::from(month_day),
+            };
+            (1..=len).contains(&day).then_some(first + day - 1)
+        })
+    }
+
+    /// The days of BYDAY in the `len` days from `first`, a month or a year.
+    fn by_day_days(&self, first: i64, len: i64) -> Vec<i64> {
+        self.by_day
+            .iter()
+            .flat_map(|by_day| weekdays_in(first, len, *by_day))
+            .collect()
+    }
+
+    /// Whether `day` is one of BYDAY in the `len` days from `first`, with the ordinals.
+    fn matches_by_day(&self, day: i64, first: i64, len: i64) -> bool {
+        self.by_day
+            .iter()
+            .any(|by_day| weekdays_in(first, len, *by_day).contains(&day))
+    }
+
+    /// Whether `day` is one of the weekdays of BYDAY, without the ordinals.
+    fn matches_weekday(&self, day: i64) -> bool {
+        self.by_day
+            .iter()
+            .any(|by_day| by_day.weekday == weekday(day))
+    }
+
+    fn matches_month_day(&self, day: i64) -> bool {
+        if self.by_month_day.is_empty() {
+            return true;
+        }
+        let (year, month, _) = civil_from_days(day);
+        self.month_days_of(year, month)
+            .any(|month_day| month_day == day)
+    }
+
+    /// The local seconds of the day of the occurrences on `day`.
+    fn day_times(&self, day: i64) -> Vec<i64> {
+        let start = self.dtstart + self.offset;
+        let start_secs = start.rem_euclid(SECS_PER_DAY);
+        let (hour, minute, second) = (start_secs / 3600, start_secs / 60 % 60, start_secs % 60);
+        let interval = i64::from(self.interval);
+
+        let hours = match self.frequency {
+            Frequency::Hourly => (0..24)
+                .filter(|h| (day * 24 + h - start.div_euclid(3600)).rem_euclid(interval) == 0)
+                .filter(|&h| self.by_hour.is_empty() || self.by_hour.contains(&(h as u32)))
+                .collect(),
+            Frequency::Minutely => (0..24)
+                .filter(|&h| self.by_hour.is_empty() || self.by_hour.contains(&(h as u32)))
+                .collect(),
+            _ if self.by_hour.is_empty() => vec![hour],
+            _ => self
+                .by_hour
+                .iter()
+                .map(|&h| i64::from(h))
+                .collect::<Vec<_>>(),
+        };
+
+        let mut times = Vec::new();
+        for h in hours {
+            let minutes = match self.frequency {
+                Frequency::Minutely => (0..60)
+                    .filter(|m| {
+                        (day * 1440 + h * 60 + m - start.div_euclid(60)).rem_euclid(interval) == 0
+                    })
+                    .filter(|&m| self.by_minute.is_empty() || self.by_minute.contains(&(m as u32)))
+                    .collect(),
+                _ if self.by_minute.is_empty() => vec![minute],
+                _ => self.by_minute.iter().map(|&m| i64::from(m)).collect(),
+            };
+            times.extend(minutes.into_iter().map(|m| h * 3600 + m * 60 + second));
+        }
+        times
+    }
+}
+
+/// The occurrences of an `RRule` in order, see `RRule::occurrences`.
+#[derive(Debug, Clone)]
+pub struct Occurrences {
+    rule: RRule,
+    /// Seconds since the epoch of the first occurrence returned, the earlier ones still count toward COUNT.
+    from: i64,
+    /// Index of the next period to expand.
+    period: i64,
+    /// Local times of the current period not yet returned.
+    pending: VecDeque<i64>,
+    /// Occurrences toward COUNT, the excluded ones too.
+    counted: u32,
+    done: bool,
+}
+
+impl Iterator for Occurrences {
+    type Item = SystemTime;
+
+    fn next(&mut self) -> Option<SystemTime> {
+        let mut empty = 0;
+        loop {
+            if let Some(local) = self.pending.pop_front() {
+                let when = local - self.rule.offset;
+                if when < self.rule.dtstart {
+                    continue;
+                }
+                let past_until = self.rule.until.is_some_and(|until| when > until);
+                let past_count = self.rule.count.is_some_and(|count| self.counted >= count);
+                if past_until || past_count {
+                    self.done = true;
+                    self.pending.clear();
+                    return None;
+                }
+                self.counted += 1;
+                let excluded = self.rule.exdates.contains(&when)
+                    || self.rule.exdays.contains(&local.div_euclid(SECS_PER_DAY));
+                if excluded || when < self.from {
+                    continue;
+                }
+                return Some(time_of(when));
+            }
+
+            if self.done || empty >= self.rule.max_empty_periods() {
+                return None;
+            }
+            match self.rule.expand(self.period) {
+                Some(times) if times.is_empty() => empty += 1,
+                Some(times) => self.pending = times.into(),
+                None => self.done = true,
+            }
+            self.period += 1;
+        }
+    }
+}
+
+impl FromStr for RRule {
+    type Err = TimerError;
+
+    /// Parse a rule with or without the `RRULE:` name, and DTSTART and EXDATE lines if any.
+    fn from_str(s: &str) -> TimerResult<Self> {
+        let (mut dtstart, mut rule, mut exdates) = (None, None, Vec::new());
+        for line in s.split_whitespace() {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.to_ascii_uppercase(), value),
+                None => ("RRULE".to_string(), line),
+            };
+            match name.as_str() {
+                "DTSTART" => dtstart = Some(parse_stamp(value)?),
+                "RRULE" => rule = Some(value),
+                "EXDATE" => exdates.extend(value.split(',').map(parse_stamp)),
+                _ if name.contains(';') => {
+                    return Err(invalid(format!("parameters are not supported: {}", name)))
+                }
+                _ => return Err(invalid(format!("unknown property {}", name))),
+            }
+        }
+        let rule = rule.ok_or_else(|| invalid("no RRULE"))?;
+
+        let (dtstart, offset) = match dtstart {
+            Some(stamp) => {
+                let offset = stamp.offset.unwrap_or(0);
+                (stamp.local - offset, offset)
+            }
+            None => (seconds_of(SystemTime::now()), 0),
+        };
+        let (mut excluded, mut exdays) = (Vec::new(), Vec::new());
+        for stamp in exdates {
+            let stamp = stamp?;
+            match stamp.date_only {
+                true => exdays.push(stamp.local.div_euclid(SECS_PER_DAY)),
+                false => excluded.push(stamp.utc(offset)),
+            }
+        }
+
+        let mut parsed = RRule {
+            dtstart,
+            offset,
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_hour: Vec::new(),
+            by_minute: Vec::new(),
+            by_set_pos: Vec::new(),
+            exdates: excluded,
+            exdays,
+        };
+        let mut frequency = None;
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("no value of {}", part)))?;
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => frequency = Some(parse_frequency(value)?),
+                "INTERVAL" => parsed.interval = parse_number(name, value, 1..=u32::MAX)?,
+                "COUNT" => parsed.count = Some(parse_number(name, value, 0..=u32::MAX)?),
+                "UNTIL" => {
+                    let until = parse_stamp(value)?;
+                    // a date includes the whole day
+                    let end = if until.date_only { SECS_PER_DAY - 1 } else { 0 };
+                    parsed.until = Some(until.utc(offset) + end);
+                }
+                "BYDAY" => {
+                    parsed.by_day = value
+                        .split(',')
+                        .map(parse_by_day)
+                        .collect::<TimerResult<_>>()?
+                }
+                "BYMONTHDAY" => parsed.by_month_day = parse_list(name, value, -31..=31)?,
+                "BYHOUR" => parsed.by_hour = parse_list(name, value, 0..=23)?,
+                "BYMINUTE" => parsed.by_minute = parse_list(name, value, 0..=59)?,
+                "BYSETPOS" => parsed.by_set_pos = parse_list(name, value, -366..=366)?,
+                "WKST" if value.eq_ignore_ascii_case("MO") => {}
+                _ => return Err(invalid(format!("{} is not supported", part))),
+            }
+        }
+        parsed.frequency = frequency.ok_or_else(|| invalid("no FREQ"))?;
+
+        if parsed.count.is_some() && parsed.until.is_some() {
+            return Err(invalid("both COUNT and UNTIL"));
+        }
+        if parsed.by_month_day.contains(&0) || parsed.by_set_pos.contains(&0) {
+            return Err(invalid("BYMONTHDAY and BYSETPOS can not be 0"));
+        }
+        let by_period = matches!(parsed.frequency, Frequency::Yearly | Frequency::Monthly);
+        if !by_period && parsed.by_day.iter().any(|by_day| by_day.ordinal.is_some()) {
+            return Err(invalid("ordinals of BYDAY need FREQ=MONTHLY or YEARLY"));
+        }
+        if parsed.frequency == Frequency::Weekly && !parsed.by_month_day.is_empty() {
+            return Err(invalid("BYMONTHDAY with FREQ=WEEKLY"));
+        }
+        if matches!(parsed.frequency, Frequency::Hourly | Frequency::Minutely)
+            && !parsed.by_set_pos.is_empty()
+        {
+            return Err(invalid("BYSETPOS with FREQ=HOURLY or MINUTELY"));
+        }
+        Ok(parsed)
+    }
+}
+
+/// A date-time of DTSTART, UNTIL or EXDATE.
+struct Stamp {
+    /// Local seconds since the epoch.
+    local: i64,
+    /// Seconds east of UTC, `None` for a local time of the rule.
+    offset: Option<i64>,
+    date_only: bool,
+}
+
+impl Stamp {
+    fn utc(&self, offset: i64) -> i64 {
+        self.local - self.offset.unwrap_or(offset)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> TimerError {
+    TimerError::InvalidRule(message.into())
+}
+
+fn parse_frequency(value: &str) -> TimerResult<Frequency> {
+    match value.to_ascii_uppercase().as_str() {
+        "YEARLY" => Ok(Frequency::Yearly),
+        "MONTHLY" => Ok(Frequency::Monthly),
+        "WEEKLY" => Ok(Frequency::Weekly),
+        "DAILY" => Ok(Frequency::Daily),
+        "HOURLY" => Ok(Frequency::Hourly),
+        "MINUTELY" => Ok(Frequency::Minutely),
+        _ => Err(invalid(format!("FREQ={} is not supported", value))),
+    }
+}
+
+fn parse_number<N: FromStr + PartialOrd>(
+    name: &str,
+    value: &str,
+    range: std::ops::RangeInclusive<N>,
+) -> TimerResult<N> {
+    value
+        .trim_start_matches('+')
+        .parse()
+        .ok()
+        .filter(|number| range.contains(number))
+        .ok_or_else(|| invalid(format!("invalid {}={}", name, value)))
+}
+
+fn parse_list<N: FromStr + PartialOrd + Clone>(
+    name: &str,
+    value: &str,
+    range: std::ops::RangeInclusive<N>,
+) -> TimerResult<Vec<N>> {
+    value
+        .split(',')
+        .map(|item| parse_number(name, item, range.clone()))
+        .collect()
+}
+
+/// Parse an entry of BYDAY like `TU`, `2TU` or `-1FR`.
+fn parse_by_day(value: &str) -> TimerResult<ByDay> {
+    if !value.is_ascii() {
+        return Err(invalid(format!("invalid BYDAY={}", value)));
+    }
+    let (ordinal, day) = value.split_at(value.len().saturating_sub(2));
+    let weekday = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"]
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(day))
+        .ok_or_else(|| invalid(format!("invalid BYDAY={}", value)))?;
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => {
+            let ordinal = parse_number("BYDAY", ordinal, -53..=53)?;
+            if ordinal == 0 {
+                return Err(invalid(format!("invalid BYDAY={}", value)));
+            }
+            Some(ordinal)
+        }
+    };
+    Ok(ByDay {
+        ordinal,
+        weekday: weekday as i64,
+    })
+}
+
+/// Parse `YYYYMMDD`, or `YYYYMMDDTHHMMSS` followed by nothing, `Z` or an offset like `+0200`.
+fn parse_stamp(value: &str) -> TimerResult<Stamp> {
+    let error = || invalid(format!("invalid date-time {}", value));
+    let digits = |range: std::ops::Range<usize>| {
+        value
+            .get(range)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<i64>().ok())
+            .ok_or_else(error)
+    };
+
+    let (year, month, day) = (digits(0..4)?, digits(4..6)?, digits(6..8)?);
+    if !(1..=12).contains(&month)
+        || !(1..=i64::from(days_in_month(year, month as u32))).contains(&day)
+    {
+        return Err(error());
+    }
+    let date = days_from_civil(year, month as u32, day as u32) * SECS_PER_DAY;
+    if value.len() == 8 {
+        return Ok(Stamp {
+            local: date,
+            offset: None,
+            date_only: true,
+        });
+    }
+
+    if value.as_bytes().get(8) != Some(&b'T') {
+        return Err(error());
+    }
+    let (hour, minute, second) = (digits(9..11)?, digits(11..13)?, digits(13..15)?);
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(error());
+    }
+    let offset = match &value[15..] {
+        "" => None,
+        "Z" | "z" => Some(0),
+        zone if zone.len() == 5 && (zone.starts_with('+') || zone.starts_with('-')) => {
+            let (hours, minutes) = (digits(16..18)?, digits(18..20)?);
+            if minutes > 59 {
+                return Err(error());
+            }
+            let offset = hours * 3600 + minutes * 60;
+            Some(if zone.starts_with('-') {
+                -offset
+            } else {
+                offset
+            })
+        }
+        _ => return Err(error()),
+    };
+    Ok(Stamp {
+        local: date + hour * 3600 + minute * 60 + second,
+        offset,
+        date_only: false,
+    })
+}
+
+/// The days of a weekday in the `len` days from `first`, only the `ordinal`th one if any.
+fn weekdays_in(first: i64, len: i64, by_day: ByDay) -> Vec<i64> {
+    let start = first + (by_day.weekday - weekday(first)).rem_euclid(7);
+    let days = (start..first + len).step_by(7).collect::<Vec<_>>();
+    match by_day.ordinal {
+        None => days,
+        Some(ordinal) => {
+            let index = match ordinal > 0 {
+                true => ordinal as i64 - 1,
+                false => days.len() as i64 + ordinal as i64,
+            };
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| days.get(index).copied())
+                .into_iter()
+                .collect()
+        }
+    }
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted + 2) / 5 + 1) as u32;
+    let month = if shifted < 10 {
+        shifted + 3
+    } else {
+        shifted - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The weekday of a number of days since 1970-01-01, from 0 for Monday.
+fn weekday(days: i64) -> i64 {
+    (days + 3).rem_euclid(7)
+}
+
+/// Whole seconds since the epoch, rounded down.
+fn seconds_of(when: SystemTime) -> i64 {
+    match when.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+fn time_of(seconds: i64) -> SystemTime {
+    match seconds >= 0 {
+        true => UNIX_EPOCH + Duration::from_secs(seconds as u64),
+        false => UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The UTC time of a date and a time, in `YYYYMMDDTHHMMSSZ`.
+    fn utc(stamp: &str) -> SystemTime {
+        time_of(parse_stamp(stamp).unwrap().utc(0))
+    }
+
+    fn occurrences(rule: &str) -> Vec<SystemTime> {
+        rule.parse::<RRule>().unwrap().occurrences().collect()
+    }
+
+    #[test]
+    fn test_civil() {
+        for days in [-719_468, -1, 0, 11_016, 20_744, MAX_DAY] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(20_744), (2026, 10, 18));
+        assert_eq!(civil_from_days(MAX_DAY), (9999, 12, 31));
+        // 2026-10-18 is a Sunday
+        assert_eq!(weekday(20_744), 6);
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = "RRULE:FREQ=MONTHLY;INTERVAL=2;BYDAY=2TU,-1FR,+1mo;BYMONTHDAY=-1;WKST=MO"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(rule.frequency, Frequency::Monthly);
+        assert_eq!(rule.interval, 2);
+        let ordinals = rule.by_day.iter().map(|by_day| by_day.ordinal);
+        assert_eq!(ordinals.collect::<Vec<_>>(), [Some(2), Some(-1), Some(1)]);
+        assert_eq!(rule.by_day[1].weekday, 4);
+        assert_eq!(rule.by_month_day, [-1]);
+
+        let rule = "DTSTART:20261020T093000-0130\nRRULE:FREQ=DAILY;UNTIL=20261101\n\
+                    EXDATE:20261021T093000,20261022T110000Z"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(rule.offset, -5400);
+        assert_eq!(time_of(rule.dtstart), utc("20261020T110000Z"));
+        assert_eq!(time_of(rule.until.unwrap()), utc("20261102T012959Z"));
+        assert_eq!(rule.exdates.len(), 2);
+        assert_eq!(time_of(rule.exdates[0]), utc("20261021T110000Z"));
+
+        for invalid in [
+            "",
+            "INTERVAL=2",
+            "FREQ=SECONDLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;BYHOUR=24",
+            "FREQ=DAILY;BYMONTH=3",
+            "FREQ=WEEKLY;BYDAY=2TU",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYDAY=0TU",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=MONTHLY;BYMONTHDAY=0",
+            "FREQ=DAILY;COUNT=2;UNTIL=20261101",
+            "FREQ=HOURLY;BYSETPOS=1",
+            "DTSTART;TZID=Europe/Paris:20261020T093000 FREQ=DAILY",
+            "DTSTART:20261320T093000 FREQ=DAILY",
+            "DTSTART:20261020T093000+02 FREQ=DAILY",
+        ] {
+            let error = invalid.parse::<RRule>().unwrap_err();
+            assert!(matches!(error, TimerError::InvalidRule(_)), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_weekday_ordinals() {
+        // 2nd Tuesday of every month at 09:30, 4 times
+        let rule = "DTSTART:20261001T093000Z\n\
+                    RRULE:FREQ=MONTHLY;BYDAY=2TU;BYHOUR=9;BYMINUTE=30;COUNT=4";
+        let expected = ["20261013", "20261110", "20261208", "20270112"];
+        let expected = expected.map(|date| utc(&format!("{}T093000Z", date)));
+        assert_eq!(occurrences(rule), expected);
+
+        // last weekday of the month, by BYSETPOS or by ordinals
+        let expected = ["20261030", "20261130", "20261231", "20270129"];
+        let expected = expected.map(|date| utc(&format!("{}T170000Z", date)));
+        let rule = "DTSTART:20261001T170000Z\n\
+                    RRULE:FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=4";
+        assert_eq!(occurrences(rule), expected);
+        let rule = "DTSTART:20261001T170000Z\n\
+                    RRULE:FREQ=MONTHLY;BYMONTHDAY=-1,-2,-3;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=4";
+        assert_eq!(occurrences(rule), expected);
+
+        // Friday the 13th, and the last Sunday of the year
+        let rule = "DTSTART:20260101T000000Z\nRRULE:FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13;COUNT=3";
+        let expected = ["20260213", "20260313", "20261113"];
+        assert_eq!(
+            occurrences(rule),
+            expected.map(|date| utc(&format!("{}T000000Z", date)))
+        );
+        let rule = "DTSTART:20260101T080000Z\nRRULE:FREQ=YEARLY;BYDAY=-1SU;COUNT=2";
+        let expected = [utc("20261227T080000Z"), utc("20271226T080000Z")];
+        assert_eq!(occurrences(rule), expected);
+    }
+
+    #[test]
+    fn test_frequencies() {
+        // every other Tuesday and Thursday at 09:30 in UTC+2, until a date
+        let rule = "DTSTART:20261020T093000+0200\n\
+                    RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;UNTIL=20261105";
+        let expected = ["20261020", "20261022", "20261103", "20261105"];
+        let expected = expected.map(|date| utc(&format!("{}T073000Z", date)));
+        assert_eq!(occurrences(rule), expected);
+
+        // the 31st, skipping the shorter months
+        let rule = "DTSTART:20260131T120000Z\nRRULE:FREQ=MONTHLY;COUNT=3";
+        let expected = ["20260131", "20260331", "20260531"];
+        assert_eq!(
+            occurrences(rule),
+            expected.map(|date| utc(&format!("{}T120000Z", date)))
+        );
+
+        // DTSTART does not match the rule, it is not an occurrence
+        let rule = "DTSTART:20261018T100000Z\nRRULE:FREQ=DAILY;BYHOUR=8,18;BYMINUTE=0,30;COUNT=5";
+        let expected = [
+            "20261018T180000Z",
+            "20261018T183000Z",
+            "20261019T080000Z",
+            "20261019T083000Z",
+            "20261019T180000Z",
+        ];
+        assert_eq!(occurrences(rule), expected.map(utc));
+
+        let rule = "DTSTART:20261018T224500Z\nRRULE:FREQ=HOURLY;INTERVAL=5;BYMINUTE=0,45;COUNT=4";
+        let expected = [
+            "20261018T224500Z",
+            "20261019T030000Z",
+            "20261019T034500Z",
+            "20261019T080000Z",
+        ];
+        assert_eq!(occurrences(rule), expected.map(utc));
+
+        let rule = "DTSTART:20261018T235000Z\nRRULE:FREQ=MINUTELY;INTERVAL=15;BYHOUR=0;COUNT=3";
+        let expected = ["20261019T000500Z", "20261019T002000Z", "20261019T003500Z"];
+        assert_eq!(occurrences(rule), expected.map(utc));
+
+        // never, it gives up after a calendar cycle
+        let rule = "DTSTART:20260101T000000Z\nRRULE:FREQ=YEARLY;BYMONTHDAY=31;BYDAY=1MO";
+        assert_eq!(occurrences(rule), []);
+    }
+
+    #[test]
+    fn test_occurrences_from() {
+        // straight to the period of the time, not through the millions of minutes before it
+        let rule = "DTSTART:20000101T000000Z
+RRULE:FREQ=MINUTELY;INTERVAL=7"
+            .parse::<RRule>()
+            .unwrap();
+        let from = utc("20261018T120000Z");
+        assert_eq!(rule.after(from), Some(utc("20261018T120300Z")));
+        let next = rule.occurrences_from(from).take(2).collect::<Vec<_>>();
+        assert_eq!(next, [utc("20261018T120300Z"), utc("20261018T121000Z")]);
+
+        let rule = "DTSTART:20261020T093000+0200
+                    RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH";
+        let rule = rule.parse::<RRule>().unwrap();
+        let from = rule.occurrences_from(utc("20261104T000000Z")).next();
+        assert_eq!(from, Some(utc("20261105T073000Z")));
+        let rule = "DTSTART:19990228T060000Z
+RRULE:FREQ=YEARLY;INTERVAL=3";
+        let rule = rule.parse::<RRule>().unwrap();
+        assert_eq!(
+            rule.after(utc("20260301T000000Z")),
+            Some(utc("20290228T060000Z"))
+        );
+
+        // the earlier occurrences still count toward COUNT
+        let rule = "DTSTART:20261018T080000Z
+RRULE:FREQ=DAILY;COUNT=3";
+        let rule = rule.parse::<RRule>().unwrap();
+        let from = rule.occurrences_from(utc("20261019T120000Z"));
+        assert_eq!(from.collect::<Vec<_>>(), [utc("20261020T080000Z")]);
+    }
+
+    #[test]
+    fn test_exdate() {
+        let rule = "DTSTART:20261019T090000+0100\nRRULE:FREQ=DAILY;COUNT=4\n\
+                    EXDATE:20261020T090000,20261021T080000Z";
+        let rule = rule.parse::<RRule>().unwrap();
+        // excluded dates count toward COUNT
+        let expected = [utc("20261019T080000Z"), utc("20261022T080000Z")];
+        assert_eq!(rule.occurrences().collect::<Vec<_>>(), expected);
+
+        let rule = rule.exdate(utc("20261022T080000Z"));
+        assert_eq!(rule.occurrences().count(), 1);
+        assert_eq!(rule.after(utc("20261019T080000Z")), None);
+
+        // a date excludes the whole local day
+        let rule = "DTSTART:20261019T090000+0100
+RRULE:FREQ=HOURLY;INTERVAL=8;COUNT=6
+                    EXDATE:20261020";
+        let expected = ["20261019T080000Z", "20261019T160000Z", "20261021T000000Z"];
+        assert_eq!(occurrences(rule), expected.map(utc));
+
+        let rule = "FREQ=DAILY;BYHOUR=9;BYMINUTE=30"
+            .parse::<RRule>()
+            .unwrap()
+            .dtstart(utc("20261018T120000Z"));
+        assert_eq!(
+            rule.after(utc("20261018T120000Z")),
+            Some(utc("20261019T093000Z"))
+        );
+        // at 09:30 of UTC-5, the same day
+        let rule = rule.utc_offset(-5 * 3600);
+        assert_eq!(
+            rule.after(utc("20261018T120000Z")),
+            Some(utc("20261018T143000Z"))
+        );
+    }
+}
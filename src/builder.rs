@@ -0,0 +1,198 @@
+use std::{collections::HashMap, fmt::Debug, hash::Hash, thread, time::Duration};
+
+use crate::time_wheel::{self, Reliable, Scheduler, TickReceiver};
+use crate::{Jitter, Rounding, MAX_SHARDS};
+
+/// Builder of a time wheel, for the settings beyond the tick interval.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::Builder;
+/// use std::time::Duration;
+///
+/// let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+///     .workers(4)
+///     .build::<String>();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    pub(crate) interval: Duration,
+    pub(crate) workers: usize,
+    pub(crate) shards: usize,
+    pub(crate) rounding: Rounding,
+    pub(crate) capacity: Option<usize>,
+    pub(crate) max_pending: Option<usize>,
+    pub(crate) tag_limits: HashMap<String, usize>,
+    pub(crate) block_when_full: Option<Duration>,
+    pub(crate) visibility_timeout: Duration,
+    pub(crate) max_redeliveries: u32,
+    pub(crate) jitter: Option<Jitter>,
+    pub(crate) seed: Option<u64>,
+}
+
+impl Builder {
+    /// New builder with a specific tick interval.
+    pub fn new(interval: Duration) -> Self {
+        Builder {
+            interval,
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            shards: 1,
+            rounding: Rounding::default(),
+            capacity: None,
+            max_pending: None,
+            tag_limits: HashMap::new(),
+            block_when_full: None,
+            visibility_timeout: Duration::from_secs(30),
+            max_redeliveries: 3,
+            jitter: None,
+            seed: None,
+        }
+    }
+
+    /// Number of workers running the jobs of `Scheduler::arrange_fn`, default is the number of CPUs.
+    ///
+    /// It is also the limit of jobs running at the same time, the others wait in the queue.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Number of independent wheels, each one runs on its own timer thread, default is 1.
+    ///
+    /// Tasks are assigned to the shards round robin, or by `InnerScheduler::shard_by`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0 or greater than 256.
+    pub fn shards(mut self, shards: usize) -> Self {
+        assert!(
+            (1..=MAX_SHARDS).contains(&shards),
+            "shards must be in 1..={}",
+            MAX_SHARDS
+        );
+        self.shards = shards;
+        self
+    }
+
+    /// How deadlines between two ticks are rounded, default is `Rounding::NeverEarly`.
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Bound every receiver to `capacity` tasks, default is unbounded.
+    ///
+    /// Expired tasks wait in the timer thread while the receiver is full, higher priorities go first.
+    pub fn bounded(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Limit the pending tasks of all shards, default is unlimited.
+    ///
+    /// Tasks on the way to the timer threads count too,
+    /// `InnerScheduler::try_at` fails with `TimerError::CapacityExceeded` past the limit.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = Some(max_pending);
+        self
+    }
+
+    /// Limit the pending tasks of `tag`, see `InnerScheduler::tag`.
+    pub fn tag_limit(mut self, tag: impl Into<String>, limit: usize) -> Self {
+        self.tag_limits.insert(tag.into(), limit);
+        self
+    }
+
+    /// Wait up to `timeout` for room when a limit is reached, instead of failing right away.
+    pub fn block_when_full(mut self, timeout: Duration) -> Self {
+        self.block_when_full = Some(timeout);
+        self
+    }
+
+    /// Add a random delay drawn from `jitter` to every `InnerScheduler::after`, default is none.
+    ///
+    /// Tasks arranged with the same delay at the same time spread out instead of expiring in one tick.
+    /// Deadlines of `InnerScheduler::at` are kept exact.
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    /// Seed the random generator of the jitter, for reproducible deadlines, default is seeded from the OS.
+    pub fn jitter_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// How long a delivered task of `build_reliable` waits for its ack before it is delivered again, default is 30 seconds.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout;
+        self
+    }
+
+    /// Times a task of `build_reliable` is delivered again without an ack
+    /// before it goes to the dead letters, default is 3.
+    pub fn max_redeliveries(mut self, max_redeliveries: u32) -> Self {
+        self.max_redeliveries = max_redeliveries;
+        self
+    }
+
+    /// Start the time wheel, expired tasks of all shards go to the same receiver.
+    pub fn build<T: Debug + Send + 'static>(self) -> (Scheduler<T>, TickReceiver<T>) {
+        let (sender, receiver) = time_wheel::channel(self.capacity);
+        let senders = vec![sender; self.shards];
+        (
+            time_wheel::start(self, senders, None),
+            TickReceiver::new(receiver, None),
+        )
+    }
+
+    /// Start the time wheel, expired tasks of shard `i` go to the `i`th receiver.
+    pub fn build_per_shard<T: Debug + Send + 'static>(
+        self,
+    ) -> (Scheduler<T>, Vec<TickReceiver<T>>) {
+        let (senders, receivers) = (0..self.shards)
+            .map(|_| time_wheel::channel(self.capacity))
+            .map(|(sender, receiver)| (sender, TickReceiver::new(receiver, None)))
+            .unzip();
+        (time_wheel::start(self, senders, None), receivers)
+    }
+
+    /// Start the time wheel with keyed tasks, see `Scheduler::arrange_keyed`.
+    pub fn build_keyed<K, T>(self) -> (Scheduler<T, K>, TickReceiver<T>)
+    where
+        K: Hash + Eq + Clone + Send + 'static,
+        T: Debug + Send + 'static,
+    {
+        let (sender, receiver) = time_wheel::channel(self.capacity);
+        let senders = vec![sender; self.shards];
+        (
+            time_wheel::start(self, senders, None),
+            TickReceiver::new(receiver, None),
+        )
+    }
+
+    /// Start the time wheel with at-least-once delivery, return the scheduler, the receiver and the dead letters.
+    ///
+    /// Every delivered entity must be acked by `TickReceiver::ack` in `visibility_timeout`,
+    /// or it is delivered again, up to `max_redeliveries` times before it goes to the dead letters.
+    /// Receive by `TickReceiver::recv_with_id` to get the id to ack.
+    pub fn build_reliable<T: Clone + Debug + Send + 'static>(
+        self,
+    ) -> (Scheduler<T>, TickReceiver<T>, TickReceiver<T>) {
+        let (sender, receiver) = time_wheel::channel(self.capacity);
+        let senders = vec![sender; self.shards];
+        let (dead_letters, dead_receiver) = crossbeam_channel::unbounded();
+        let reliable = Reliable {
+            visibility_timeout: self.visibility_timeout,
+            max_redeliveries: self.max_redeliveries,
+            cloner: T::clone,
+            dead_letters,
+        };
+
+        let scheduler = time_wheel::start(self, senders, Some(reliable));
+        let receiver = TickReceiver::new(receiver, scheduler.acker());
+        (scheduler, receiver, TickReceiver::new(dead_receiver, None))
+    }
+}
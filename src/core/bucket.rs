@@ -1,5 +1,4 @@
 use super::slot::{Entity, Slot};
-use std::convert::TryInto;
 use std::fmt::Debug;
 
 /// power of 2 (2^6 = 64)
@@ -26,16 +25,12 @@ pub(crate) struct Bucket<T> {
     _level: u32,
 }
 
-impl<T: Debug> Bucket<T> {
+impl<T> Bucket<T> {
     /// New bucket `level` is from 0.
     pub fn new(level: u32) -> Self {
         let step_size_in_bits = SLOT_NUM_POWER_OF_2 * level;
 
-        let slots = (0..SLOT_NUM)
-            .map(|_| Slot::<T>::new())
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+        let slots = std::array::from_fn(|_| Slot::<T>::new());
         Bucket {
             occupied: 0,
             cursor: 0,
@@ -47,7 +42,8 @@ impl<T: Debug> Bucket<T> {
         }
     }
 
-    pub fn add(&mut self, entity: Entity<T>, offset: u64) {
+    /// add entity, return the index of the slot it is placed in
+    pub fn add(&mut self, entity: Entity<T>, offset: u64) -> u32 {
         debug_assert!(offset > 0, "tick times is not allow zero");
 
         // TODO: there will be panic, tick_times小于1了
@@ -62,6 +58,19 @@ impl<T: Debug> Bucket<T> {
         let slot_index_from_cur = (slot_index + self.cursor) & SLOT_NUM_MASK;
 
         self.slots[slot_index_from_cur as usize].push(entity);
+        slot_index_from_cur
+    }
+
+    /// remove the entity with `id` from the slot at `slot_index`
+    pub fn remove(&mut self, slot_index: u32, id: u64) -> Option<Entity<T>> {
+        let slot = &mut self.slots[slot_index as usize];
+        let entity = slot.remove(id)?;
+
+        let slot_index_from_cur = slot_index.wrapping_sub(self.cursor) & SLOT_NUM_MASK;
+        if slot.items.is_none() && slot_index_from_cur > 0 {
+            self.occupied &= !(1 << (slot_index_from_cur - 1));
+        }
+        Some(entity)
     }
 
     /// tick (result, next level tick times)
@@ -87,7 +96,7 @@ impl<T: Debug> Bucket<T> {
                     temp_entities.extend(timeout_entities);
                 }
                 left_times -= ticks;
-                self.occupied = self.occupied >> ticks;
+                self.occupied >>= ticks;
             }
 
             entities = Some(temp_entities);
@@ -96,7 +105,7 @@ impl<T: Debug> Bucket<T> {
         self.occupied = self.occupied.checked_shr(left_times).unwrap_or(0);
         self.cursor = (self.cursor + left_times) & SLOT_NUM_MASK;
 
-        return (entities, next_level_tick_times);
+        (entities, next_level_tick_times)
     }
 
     /// get the non-stop ticks
@@ -112,6 +121,20 @@ impl<T: Debug> Bucket<T> {
     }
 }
 
+impl<T> Bucket<T> {
+    /// get the entity with `id` from the slot at `slot_index`
+    pub fn get(&self, slot_index: u32, id: u64) -> Option<&Entity<T>> {
+        self.slots[slot_index as usize].get(id)
+    }
+
+    /// all entities in the slots
+    pub fn entities(&self) -> impl Iterator<Item = &Entity<T>> {
+        self.slots
+            .iter()
+            .flat_map(|slot| slot.items.iter().flatten())
+    }
+}
+
 // Test
 #[cfg(test)]
 mod tests {
@@ -121,15 +144,18 @@ mod tests {
     macro_rules! content {
         ($item:expr) => {
             Entity {
+                id: $item,
                 data: $item,
                 tick_times: $item,
                 when: SystemTime::now(),
                 offset: 0,
                 ticks: 0,
+                priority: 0,
             }
         };
         ($item:expr, $times: expr) => {
             Entity {
+                id: $item,
                 data: $item,
                 tick_times: $times,
             }
@@ -183,6 +209,22 @@ mod tests {
         assert_eq!(items[0].data, 128);
     }
 
+    #[test]
+    fn test_remove() {
+        let mut bucket = Bucket::<u64>::new(0);
+        let slot = bucket.add(content!(5), 5);
+        bucket.add(content!(105), 5);
+        assert_eq!(slot, 5);
+
+        assert_eq!(bucket.remove(slot, 7), None);
+        assert_eq!(bucket.remove(slot, 5).unwrap().data, 5);
+        assert_eq!(bucket.occupied, 0b0001_0000);
+
+        assert_eq!(bucket.remove(slot, 105).unwrap().data, 105);
+        assert_eq!(bucket.occupied, 0);
+        assert_eq!(bucket.tick(5).0, None);
+    }
+
     #[test]
     fn test_tick() {
         let mut bucket = Bucket::<u64>::new(0);
@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime};
+
+use crate::Rounding;
+
+/// Maps deadlines to ticks.
+///
+/// Ticks are counted from the start instead of from the time a task is arranged,
+/// so the remainder of a deadline that is not a multiple of the interval never accumulates.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Clock {
+    start_at: SystemTime,
+    interval_in_nanos: u128,
+    rounding: Rounding,
+}
+
+impl Clock {
+    pub(crate) fn new(start_at: SystemTime, interval: Duration, rounding: Rounding) -> Self {
+        Clock {
+            start_at,
+            interval_in_nanos: interval.as_nanos().max(1),
+            rounding,
+        }
+    }
+
+    /// The tick `when` expires in.
+    pub(crate) fn tick_of(&self, when: SystemTime) -> u64 {
+        let nanos = when
+            .duration_since(self.start_at)
+            .unwrap_or_default()
+            .as_nanos();
+        let interval = self.interval_in_nanos;
+
+        let ticks = match self.rounding {
+            Rounding::NeverEarly => nanos.div_ceil(interval),
+            Rounding::Nearest => (nanos + interval / 2) / interval,
+            Rounding::NeverLate => nanos / interval,
+        };
+        ticks as u64
+    }
+
+    /// Whether a task expiring at `when` must be held back at `now`.
+    pub(crate) fn is_early(&self, when: SystemTime, now: SystemTime) -> bool {
+        self.rounding == Rounding::NeverEarly && when > now
+    }
+
+    /// Ticks to wait from `now` to `when`, at least one.
+    pub(crate) fn ticks_until(&self, when: SystemTime, now: SystemTime) -> u64 {
+        let nanos = when.duration_since(now).unwrap_or_default().as_nanos();
+        (nanos.div_ceil(self.interval_in_nanos) as u64).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_of() {
+        let start_at = SystemTime::now();
+        let interval = Duration::from_millis(10);
+        let at = |millis| start_at + Duration::from_millis(millis);
+
+        let clock = Clock::new(start_at, interval, Rounding::NeverEarly);
+        assert_eq!(clock.tick_of(at(0)), 0);
+        assert_eq!(clock.tick_of(at(1)), 1);
+        assert_eq!(clock.tick_of(at(10)), 1);
+        assert_eq!(clock.tick_of(at(14)), 2);
+        assert_eq!(clock.tick_of(start_at - interval), 0);
+
+        let clock = Clock::new(start_at, interval, Rounding::Nearest);
+        assert_eq!(clock.tick_of(at(14)), 1);
+        assert_eq!(clock.tick_of(at(15)), 2);
+
+        let clock = Clock::new(start_at, interval, Rounding::NeverLate);
+        assert_eq!(clock.tick_of(at(19)), 1);
+        assert_eq!(clock.tick_of(at(20)), 2);
+    }
+
+    #[test]
+    fn test_early() {
+        let now = SystemTime::now();
+        let interval = Duration::from_millis(10);
+
+        let clock = Clock::new(now, interval, Rounding::NeverEarly);
+        assert!(clock.is_early(now + Duration::from_nanos(1), now));
+        assert!(!clock.is_early(now, now));
+        assert_eq!(clock.ticks_until(now + Duration::from_nanos(1), now), 1);
+        assert_eq!(clock.ticks_until(now + Duration::from_millis(25), now), 3);
+
+        let clock = Clock::new(now, interval, Rounding::Nearest);
+        assert!(!clock.is_early(now + Duration::from_millis(1), now));
+    }
+}
@@ -1,7 +1,10 @@
 // src/core/mod.rs
 
 mod bucket;
+mod clock;
 mod slot;
 mod wheel;
 
+pub(crate) use clock::Clock;
+pub(crate) use slot::Entity;
 pub(crate) use wheel::Wheel;
@@ -2,11 +2,13 @@ use std::time::SystemTime;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Entity<T> {
+    pub(crate) id: u64,
     pub data: T,
     pub(crate) tick_times: u64,
     pub(crate) when: SystemTime,
     pub(crate) ticks: u64,
     pub(crate) offset: u64,
+    pub(crate) priority: u8,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -27,6 +29,20 @@ impl<T> Slot<T> {
 
         // self.items.as_mut().unwrap().push(item);
     }
+
+    pub(crate) fn get(&self, id: u64) -> Option<&Entity<T>> {
+        self.items.as_ref()?.iter().find(|item| item.id == id)
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<Entity<T>> {
+        let items = self.items.as_mut()?;
+        let position = items.iter().position(|item| item.id == id)?;
+        let item = items.remove(position);
+        if items.is_empty() {
+            self.items = None;
+        }
+        Some(item)
+    }
 }
 
 #[cfg(test)]
@@ -36,7 +52,7 @@ mod test {
         use super::Entity;
 
         use std::mem::{align_of, size_of};
-        assert_eq!(size_of::<Entity<String>>(), 32);
+        assert_eq!(size_of::<Entity<String>>(), 80);
         assert_eq!(align_of::<Entity<String>>(), 8);
     }
 }
@@ -1,53 +1,125 @@
-use timed::timed;
-
-use super::{bucket::Bucket, slot::Entity};
+use super::{bucket::Bucket, slot::Entity, Clock};
 use std::{
-    convert::TryInto,
+    cmp::Reverse,
+    collections::HashMap,
     fmt::Debug,
+    mem,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 const LEVEL_COUNT: usize = 6;
 
+/// Where a pending entity is placed.
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// (level, slot index)
+    Bucket(usize, u32),
+    Homeless,
+}
+
 pub struct Wheel<T> {
     buckets: [Bucket<T>; LEVEL_COUNT],
     pub(crate) ticks: u64,
     homeless: Option<Vec<Entity<T>>>,
-    _notice: Box<dyn Fn(T)>,
+    /// Location of every pending entity by id.
+    index: HashMap<u64, Location>,
+    /// Expired entities waiting to be noticed in order.
+    due: Vec<Entity<T>>,
+    /// Checks the deadlines on delivery, ticks are not bound to time without it.
+    clock: Option<Clock>,
+    _notice: Box<dyn Fn(Entity<T>)>,
 }
 
-impl<T: Debug> Wheel<T> {
-    pub(crate) fn new(notice: impl Fn(T) + 'static) -> Self {
-        let buckets = (0..LEVEL_COUNT)
-            .map(|level| Bucket::new(level as u32))
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+impl<T> Wheel<T> {
+    /// New wheel only counting ticks, `notice` takes the expired entities.
+    pub(crate) fn new(notice: impl Fn(Entity<T>) + 'static) -> Self {
+        Self::new_with_clock(notice, None)
+    }
+
+    /// New wheel holding back the entities expired earlier than their deadlines by `clock`.
+    pub(crate) fn with_clock(notice: impl Fn(Entity<T>) + 'static, clock: Clock) -> Self {
+        Self::new_with_clock(notice, Some(clock))
+    }
+
+    fn new_with_clock(notice: impl Fn(Entity<T>) + 'static, clock: Option<Clock>) -> Self {
+        let buckets = std::array::from_fn(|level| Bucket::new(level as u32));
         Wheel {
             buckets,
             ticks: 0,
             homeless: None,
+            index: HashMap::new(),
+            due: Vec::new(),
+            clock,
             _notice: Box::new(notice),
         }
     }
 
-    #[timed]
-    pub(crate) fn schedule(&mut self, entity: T, offset: u64, when: SystemTime) {
+    /// Schedule an entity expiring `offset` ticks later, an entity with 0 offset waits for `flush`.
+    ///
+    /// Ids break the ties of `when`, an entity moved to another `when` keeps its id,
+    /// so it is ordered by the id it is first scheduled with.
+    pub(crate) fn schedule(
+        &mut self,
+        id: u64,
+        entity: T,
+        offset: u64,
+        when: SystemTime,
+        priority: u8,
+    ) {
         let entity = Entity {
+            id,
             data: entity,
             tick_times: offset + self.ticks,
             when,
-            offset: offset,
+            offset,
             ticks: self.ticks,
+            priority,
         };
 
-        match to_level(offset) {
-            Some(level) => self.buckets[level].add(entity, offset as u64),
-            _ => self.homeless.get_or_insert_with(Vec::new).push(entity),
+        if offset == 0 {
+            self.due.push(entity);
+        } else {
+            self.place(entity, offset);
+        }
+    }
+
+    /// Schedule a batch of `(id, entity, offset, when, priority)` in a single pass.
+    pub(crate) fn schedule_many(&mut self, entities: Vec<(u64, T, u64, SystemTime, u8)>) {
+        self.index.reserve(entities.len());
+        for (id, data, offset, when, priority) in entities {
+            self.schedule(id, data, offset, when, priority);
+        }
+    }
+
+    /// Remove the pending entity with `id`, wherever it is placed.
+    pub(crate) fn cancel(&mut self, id: u64) -> Option<Entity<T>> {
+        match self.index.remove(&id) {
+            Some(Location::Bucket(level, slot_index)) => self.buckets[level].remove(slot_index, id),
+            Some(Location::Homeless) => {
+                let homeless = self.homeless.as_mut()?;
+                let position = homeless.iter().position(|entity| entity.id == id)?;
+                Some(homeless.remove(position))
+            }
+            // expired, but not noticed yet, `flush` sorts them anyway
+            None => {
+                let position = self.due.iter().position(|entity| entity.id == id)?;
+                Some(self.due.swap_remove(position))
+            }
         }
     }
 
-    #[timed]
+    fn place(&mut self, entity: Entity<T>, offset: u64) {
+        let id = entity.id;
+        let location = match to_level(offset) {
+            Some(level) => Location::Bucket(level, self.buckets[level].add(entity, offset)),
+            _ => {
+                self.homeless.get_or_insert_with(Vec::new).push(entity);
+                Location::Homeless
+            }
+        };
+        self.index.insert(id, location);
+    }
+
     pub(crate) fn tick_to(&mut self, ticks: u64) {
         if ticks <= self.ticks {
             return;
@@ -61,7 +133,9 @@ impl<T: Debug> Wheel<T> {
 
         for level in 0..LEVEL_COUNT {
             let (result, next_level_tick_times) = self.buckets[level].tick(times);
-            result.map(|entities| self.dispose_of(entities));
+            if let Some(entities) = result {
+                self.dispose_of(entities);
+            }
             tick_to_max_level = level == MAX_LEVEL_INDEX;
             if next_level_tick_times == 0 {
                 break;
@@ -71,9 +145,46 @@ impl<T: Debug> Wheel<T> {
         }
 
         if tick_to_max_level {
-            self.homeless
-                .take()
-                .map(|entities| self.dispose_of(entities));
+            if let Some(entities) = self.homeless.take() {
+                self.dispose_of(entities);
+            }
+        }
+
+        self.flush();
+    }
+
+    /// Notice the expired entities tick by tick, ordered by priority (higher first), `when`,
+    /// then by the order they are scheduled in a tick.
+    ///
+    /// Entities come from different levels, slots and the homeless, and from every tick passed at once,
+    /// sort them all together, a later tick never goes before an earlier one.
+    /// With a clock, entities whose deadlines are not reached yet are put back to the wheel.
+    pub(crate) fn flush(&mut self) {
+        let mut due = mem::take(&mut self.due);
+        due.sort_unstable_by_key(|entity| {
+            (
+                entity.tick_times,
+                Reverse(entity.priority),
+                entity.when,
+                entity.id,
+            )
+        });
+
+        let now = SystemTime::now();
+        for mut entity in due.drain(..) {
+            match self.clock {
+                Some(clock) if clock.is_early(entity.when, now) => {
+                    let offset = clock.ticks_until(entity.when, now);
+                    entity.tick_times = self.ticks + offset;
+                    self.place(entity, offset);
+                }
+                _ => self.notice(entity),
+            }
+        }
+
+        // keep the capacity for next tick
+        if self.due.is_empty() {
+            self.due = due;
         }
     }
 
@@ -94,13 +205,13 @@ impl<T: Debug> Wheel<T> {
     fn dispose_of(&mut self, entities: Vec<Entity<T>>) {
         let ticks = self.ticks;
         for entity in entities {
-            if entity.tick_times <= ticks as u64 {
-                self.notice(entity);
+            if entity.tick_times <= ticks {
+                self.index.remove(&entity.id);
+                self.due.push(entity);
             } else {
                 // add to wheel again
                 let offset = entity.tick_times - ticks;
-                let level = to_level(offset);
-                self.buckets[level.unwrap()].add(entity, offset);
+                self.place(entity, offset);
             }
         }
     }
@@ -109,26 +220,59 @@ impl<T: Debug> Wheel<T> {
         let now = SystemTime::now();
         let when = entity.when;
 
-        let mut dis = 0u128;
-        if when > now {
-            dis = when.duration_since(now).unwrap().as_micros();
+        let dis = if when > now {
+            when.duration_since(now).unwrap().as_micros()
         } else {
-            dis = now.duration_since(when).unwrap().as_micros();
-        }
+            now.duration_since(when).unwrap().as_micros()
+        };
 
-        println!(
+        log::trace!(
             "notice entity ticks: {}, system ticks:{}, time diff: {}, add offset:{}, ticks:{}",
-            entity.tick_times, self.ticks, dis, entity.offset, entity.ticks
+            entity.tick_times,
+            self.ticks,
+            dis,
+            entity.offset,
+            entity.ticks
         );
 
         assert!(self.ticks >= entity.tick_times);
 
-        (self._notice)(entity.data);
+        (self._notice)(entity);
+    }
+}
+
+impl<T> Wheel<T> {
+    /// The pending entity with `id`, wherever it is placed.
+    pub(crate) fn get(&self, id: u64) -> Option<&Entity<T>> {
+        match self.index.get(&id) {
+            Some(Location::Bucket(level, slot_index)) => self.buckets[*level].get(*slot_index, id),
+            Some(Location::Homeless) => self
+                .homeless
+                .as_ref()?
+                .iter()
+                .find(|entity| entity.id == id),
+            // expired, but not noticed yet
+            None => self.due.iter().find(|entity| entity.id == id),
+        }
+    }
+
+    /// Number of pending entities.
+    pub(crate) fn len(&self) -> usize {
+        self.index.len() + self.due.len()
+    }
+
+    /// All pending entities, in no particular order.
+    pub(crate) fn entities(&self) -> impl Iterator<Item = &Entity<T>> {
+        self.buckets
+            .iter()
+            .flat_map(Bucket::entities)
+            .chain(self.homeless.iter().flatten())
+            .chain(&self.due)
     }
 }
 
 fn to_level(offset: u64) -> Option<usize> {
-    const SIZE_OF_LEVEL_0: u64 = 1 << (6 * 1);
+    const SIZE_OF_LEVEL_0: u64 = 1 << 6;
     const SIZE_OF_LEVEL_1: u64 = 1 << (6 * 2);
     const SIZE_OF_LEVEL_2: u64 = 1 << (6 * 3);
     const SIZE_OF_LEVEL_3: u64 = 1 << (6 * 4);
@@ -171,16 +315,200 @@ mod tests {
     fn test_next_ticks() {
         let mut wheel = Wheel::<u32>::new(|_| {});
 
-        wheel.schedule(1, (64 * 64) + 1, SystemTime::now());
+        wheel.schedule(1, 1, (64 * 64) + 1, SystemTime::now(), 0);
         assert_eq!(wheel.next_ticks(), (64 * 64));
 
-        wheel.schedule(1, (64 * 64), SystemTime::now());
+        wheel.schedule(2, 1, 64 * 64, SystemTime::now(), 0);
         assert_eq!(wheel.next_ticks(), (64 * 64));
 
-        wheel.schedule(1, (64 * 64) - 1, SystemTime::now());
+        wheel.schedule(3, 1, (64 * 64) - 1, SystemTime::now(), 0);
         assert_eq!(wheel.next_ticks(), (64 * (64 - 2)));
     }
 
+    #[test]
+    fn test_notice_order() {
+        use std::{cell::RefCell, rc::Rc, time::Duration};
+
+        let noticed = Rc::new(RefCell::new(Vec::new()));
+        let noticed_copy = noticed.clone();
+        let mut wheel =
+            Wheel::<u32>::new(move |entity| noticed_copy.borrow_mut().push(entity.data));
+
+        let when = SystemTime::now();
+        let earlier = when - Duration::from_millis(1);
+
+        // same tick, cascaded from level 1 and placed at level 0 directly
+        wheel.schedule(1, 1, 100, when, 0);
+        wheel.tick_to(50);
+        wheel.schedule(2, 2, 50, when, 0);
+        wheel.schedule(3, 3, 50, earlier, 0);
+        // same tick, from the homeless
+        wheel.schedule(4, 4, (1 << 36) + 10, when, 0);
+        wheel.schedule(5, 5, (1 << 36) + 10, earlier, 0);
+
+        wheel.tick_to(100);
+        assert_eq!(*noticed.borrow(), vec![3, 1, 2]);
+
+        noticed.borrow_mut().clear();
+        while wheel.ticks + (1 << 30) < (1 << 36) + 60 {
+            wheel.tick_to(wheel.ticks + (1 << 30));
+        }
+        wheel.tick_to((1 << 36) + 59);
+        assert!(noticed.borrow().is_empty());
+        wheel.tick_to((1 << 36) + 60);
+        wheel.schedule(6, 6, 0, when, 0);
+        wheel.schedule(7, 7, 0, earlier, 0);
+        wheel.flush();
+        assert_eq!(*noticed.borrow(), vec![5, 4, 7, 6]);
+    }
+
+    #[test]
+    fn test_hold_back_early() {
+        use crate::Rounding;
+        use std::{cell::RefCell, rc::Rc, time::Duration};
+
+        let noticed = Rc::new(RefCell::new(Vec::new()));
+        let noticed_copy = noticed.clone();
+        let now = SystemTime::now();
+        let clock = Clock::new(now, Duration::from_secs(1), Rounding::NeverEarly);
+        let mut wheel = Wheel::<u32>::with_clock(
+            move |entity| noticed_copy.borrow_mut().push(entity.data),
+            clock,
+        );
+
+        // the tick is reached but the deadline is not
+        wheel.schedule(1, 1, 2, now + Duration::from_secs(60), 0);
+        wheel.schedule(2, 2, 2, now, 0);
+        wheel.tick_to(2);
+        assert_eq!(*noticed.borrow(), vec![2]);
+        assert!(wheel.index.contains_key(&1));
+    }
+
+    #[test]
+    fn test_notice_priority() {
+        use std::{cell::RefCell, rc::Rc, time::Duration};
+
+        let noticed = Rc::new(RefCell::new(Vec::new()));
+        let noticed_copy = noticed.clone();
+        let mut wheel =
+            Wheel::<u32>::new(move |entity| noticed_copy.borrow_mut().push(entity.data));
+
+        let when = SystemTime::now();
+        wheel.schedule(1, 1, 10, when - Duration::from_millis(1), 0);
+        wheel.schedule(2, 2, 10, when, 0);
+        wheel.schedule(3, 3, 10, when, 5);
+        wheel.schedule(4, 4, 10, when, 9);
+        wheel.schedule(5, 5, 10, when, 5);
+
+        wheel.tick_to(10);
+        assert_eq!(*noticed.borrow(), vec![4, 3, 5, 1, 2]);
+
+        // ticks passed at once, priority only counts in a tick
+        noticed.borrow_mut().clear();
+        wheel.schedule(6, 6, 20, when, 9);
+        wheel.schedule(7, 7, 10, when + Duration::from_millis(1), 0);
+        wheel.schedule(8, 8, 20, when, 0);
+        wheel.tick_to(30);
+        assert_eq!(*noticed.borrow(), vec![7, 6, 8]);
+    }
+
+    #[test]
+    fn test_schedule_many() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let noticed = Rc::new(RefCell::new(Vec::new()));
+        let noticed_copy = noticed.clone();
+        let mut wheel =
+            Wheel::<u32>::new(move |entity| noticed_copy.borrow_mut().push(entity.data));
+
+        let now = SystemTime::now();
+        wheel.schedule_many(vec![
+            (1, 1, 10, now, 0),
+            (2, 2, 100, now, 0),
+            (3, 3, 5000, now, 0),
+        ]);
+        assert_eq!(wheel.index.len(), 3);
+
+        wheel.tick_to(100);
+        assert_eq!(*noticed.borrow(), vec![1, 2]);
+        wheel.tick_to(5000);
+        assert_eq!(*noticed.borrow(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let noticed = Rc::new(RefCell::new(Vec::new()));
+        let noticed_copy = noticed.clone();
+        let mut wheel =
+            Wheel::<u32>::new(move |entity| noticed_copy.borrow_mut().push(entity.data));
+
+        wheel.schedule(1, 1, 10, SystemTime::now(), 0);
+        wheel.schedule(2, 2, 100, SystemTime::now(), 0);
+        wheel.schedule(3, 3, 1 << 36, SystemTime::now(), 0);
+        assert_eq!(wheel.index.len(), 3);
+
+        assert_eq!(wheel.cancel(2).unwrap().data, 2);
+        assert_eq!(wheel.cancel(3).unwrap().data, 3);
+        assert!(wheel.cancel(2).is_none());
+        assert_eq!(wheel.index.len(), 1);
+
+        wheel.tick_to(200);
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(wheel.index.len(), 0);
+        assert!(wheel.cancel(1).is_none());
+
+        // expired, waiting for the flush
+        wheel.schedule(4, 4, 0, SystemTime::now(), 0);
+        assert_eq!(wheel.cancel(4).unwrap().data, 4);
+        wheel.flush();
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn test_get() {
+        use std::time::Duration;
+
+        let mut wheel = Wheel::<u32>::new(|_| {});
+        let now = SystemTime::now();
+
+        wheel.schedule(1, 1, 0, now, 0);
+        wheel.schedule(2, 2, 100, now + Duration::from_secs(1), 0);
+        wheel.schedule(3, 3, 1 << 36, now + Duration::from_secs(2), 0);
+
+        assert_eq!(wheel.get(1).unwrap().when, now);
+        assert_eq!(wheel.get(2).unwrap().when, now + Duration::from_secs(1));
+        assert_eq!(wheel.get(3).unwrap().data, 3);
+
+        // moved down to a lower level
+        wheel.tick_to(70);
+        assert!(wheel.get(1).is_none());
+        assert_eq!(wheel.get(2).unwrap().data, 2);
+        wheel.tick_to(100);
+        assert!(wheel.get(2).is_none());
+    }
+
+    #[test]
+    fn test_entities() {
+        let mut wheel = Wheel::<u32>::new(|_| {});
+
+        wheel.schedule(1, 1, 0, SystemTime::now(), 0);
+        wheel.schedule(2, 2, 100, SystemTime::now(), 0);
+        wheel.schedule(3, 3, 1 << 36, SystemTime::now(), 0);
+        wheel.schedule(4, 4, 5, SystemTime::now(), 0);
+        assert_eq!(wheel.len(), 4);
+
+        let mut ids = wheel.entities().map(|entity| entity.id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+
+        wheel.tick_to(10);
+        assert_eq!(wheel.len(), 2);
+        assert_eq!(wheel.entities().count(), 2);
+    }
+
     #[test]
     #[ignore] // this test fn will spend 100 seconds
     fn homeless_test() {
@@ -0,0 +1,281 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use crate::time_wheel::Alarm;
+use crate::TimerId;
+
+struct Trip {
+    /// Dropped when the token trips, which disconnects the receivers of `done`.
+    armed: Option<Sender<()>>,
+    cancelled: bool,
+    /// The timer of the token, `None` if it trips with its parent.
+    timer: Option<TimerId>,
+    children: Vec<Weak<State>>,
+}
+
+struct State {
+    deadline: SystemTime,
+    trip: Mutex<Trip>,
+    done: Receiver<()>,
+    alarm: Arc<dyn Alarm>,
+    /// Kept alive while a child may still trip with it.
+    _parent: Option<Arc<State>>,
+}
+
+impl State {
+    fn new(alarm: Arc<dyn Alarm>, deadline: SystemTime, parent: Option<Arc<State>>) -> Arc<Self> {
+        let (armed, done) = crossbeam_channel::bounded(0);
+        Arc::new(State {
+            deadline,
+            trip: Mutex::new(Trip {
+                armed: Some(armed),
+                cancelled: false,
+                timer: None,
+                children: Vec::new(),
+            }),
+            done,
+            alarm,
+            _parent: parent,
+        })
+    }
+
+    /// Trip the token and its children, return their timers which may be still pending.
+    fn trip(&self, cancelled: bool) -> Vec<TimerId> {
+        let (timer, children) = {
+            let mut trip = self.trip.lock().unwrap();
+            if trip.armed.take().is_none() {
+                return Vec::new();
+            }
+            trip.cancelled = cancelled;
+            (trip.timer.take(), mem::take(&mut trip.children))
+        };
+
+        let mut timers = timer.into_iter().collect::<Vec<_>>();
+        for child in children.iter().filter_map(Weak::upgrade) {
+            timers.extend(child.trip(cancelled));
+        }
+        timers
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        // nobody waits for the token any more
+        if let Some(timer) = self.trip.get_mut().ok().and_then(|trip| trip.timer.take()) {
+            self.alarm.discard(timer);
+        }
+    }
+}
+
+/// A token tripped at its deadline right in the timer thread, or by `cancel`.
+///
+/// Workers check `is_expired` between steps, or block on `wait` or on the `done` channel
+/// together with their work. Tokens share the timer threads of their scheduler,
+/// and a child token trips at its own deadline or its parent's, whichever comes first.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::time_wheel;
+/// use std::time::Duration;
+///
+/// let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+/// let request = scheduler.deadline_token(Duration::from_secs(60));
+/// let step = request.child(Duration::from_millis(10));
+///
+/// step.wait();
+/// assert!(step.is_expired());
+/// assert!(!request.is_expired());
+///
+/// request.cancel();
+/// assert!(request.is_cancelled());
+/// ```
+#[derive(Clone)]
+pub struct DeadlineToken {
+    state: Arc<State>,
+}
+
+impl DeadlineToken {
+    pub(crate) fn new(alarm: Arc<dyn Alarm>, deadline: SystemTime) -> Self {
+        let token = DeadlineToken {
+            state: State::new(alarm, deadline, None),
+        };
+        token.arm();
+        token
+    }
+
+    /// Set the timer tripping the token at its deadline, in the timer thread.
+    fn arm(&self) {
+        let state = Arc::downgrade(&self.state);
+        let alarm = self.state.alarm.clone();
+        let timer = self.state.alarm.set(
+            self.state.deadline,
+            Box::new(move || {
+                if let Some(state) = state.upgrade() {
+                    // the timer thread can not wait for itself to cancel them
+                    for timer in state.trip(false) {
+                        alarm.discard(timer);
+                    }
+                }
+            }),
+        );
+        let mut trip = self.state.trip.lock().unwrap();
+        if trip.armed.is_some() {
+            trip.timer = Some(timer);
+        }
+    }
+
+    /// A child token tripping after `after`, or with this token if it is earlier.
+    pub fn child(&self, after: Duration) -> DeadlineToken {
+        let deadline = (SystemTime::now() + after).min(self.state.deadline);
+        let child = DeadlineToken {
+            state: State::new(self.state.alarm.clone(), deadline, Some(self.state.clone())),
+        };
+
+        {
+            let mut trip = self.state.trip.lock().unwrap();
+            if trip.armed.is_none() {
+                let cancelled = trip.cancelled;
+                drop(trip);
+                child.state.trip(cancelled);
+                return child;
+            }
+            trip.children.retain(|child| child.strong_count() > 0);
+            trip.children.push(Arc::downgrade(&child.state));
+        }
+
+        // trips with this token otherwise
+        if deadline < self.state.deadline {
+            child.arm();
+        }
+        child
+    }
+
+    /// When the token trips by itself.
+    pub fn deadline(&self) -> SystemTime {
+        self.state.deadline
+    }
+
+    /// Time left until the deadline, zero once it is tripped.
+    pub fn remaining(&self) -> Duration {
+        if self.is_expired() {
+            return Duration::ZERO;
+        }
+        self.state
+            .deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Whether the token is tripped, by its deadline, a parent or `cancel`.
+    pub fn is_expired(&self) -> bool {
+        self.state.trip.lock().unwrap().armed.is_none()
+    }
+
+    /// Whether the token is tripped by `cancel`, of itself or of a parent.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.trip.lock().unwrap().cancelled
+    }
+
+    /// Trip the token and its children now.
+    pub fn cancel(&self) {
+        for timer in self.state.trip(true) {
+            self.state.alarm.cancel(timer);
+        }
+    }
+
+    /// Block until the token is tripped.
+    pub fn wait(&self) {
+        let _ = self.state.done.recv();
+    }
+
+    /// Block until the token is tripped or `timeout` elapses, return `true` if it is tripped.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        !matches!(
+            self.state.done.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        )
+    }
+
+    /// A receiver disconnected when the token trips, to `select!` on together with the work.
+    pub fn done(&self) -> Receiver<()> {
+        self.state.done.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time_wheel;
+    use crossbeam_channel::select;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_deadline() {
+        let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+        let token = scheduler.deadline_token(Duration::from_millis(30));
+        assert!(!token.is_expired());
+        assert!(token.remaining() > Duration::from_millis(20));
+        assert!(!token.wait_timeout(Duration::from_millis(5)));
+
+        let (work, jobs) = crossbeam_channel::unbounded::<u32>();
+        work.send(1).unwrap();
+        let mut done = 0;
+        loop {
+            select! {
+                recv(jobs) -> job => done += job.unwrap(),
+                recv(token.done()) -> _ => break,
+            }
+        }
+        assert_eq!(done, 1);
+        assert!(token.is_expired());
+        assert!(!token.is_cancelled());
+        assert_eq!(token.remaining(), Duration::ZERO);
+        assert_eq!(scheduler.timer_count(), 0);
+
+        // a dropped token takes its timer with it
+        let token = scheduler.deadline_token(Duration::from_secs(60));
+        assert_eq!(scheduler.timer_count(), 1);
+        drop(token);
+        assert_eq!(scheduler.timer_count(), 0);
+
+        // a child left alone still trips at the deadline of its parent
+        let child = scheduler
+            .deadline_token(Duration::from_millis(10))
+            .child(Duration::from_secs(60));
+        assert!(child.wait_timeout(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_children() {
+        let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+        let parent = scheduler.deadline_token(Duration::from_millis(40));
+        let early = parent.child(Duration::from_millis(10));
+        let late = parent.child(Duration::from_secs(60));
+        assert_eq!(late.deadline(), parent.deadline());
+
+        let start = Instant::now();
+        early.wait();
+        assert!(!parent.is_expired());
+        late.wait();
+        assert!(parent.is_expired());
+        assert!(start.elapsed() >= Duration::from_millis(25));
+
+        // cancelled with the parent, the pending timers go too
+        let parent = scheduler.deadline_token(Duration::from_secs(60));
+        let child = parent.child(Duration::from_secs(30));
+        let grandchild = child.child(Duration::from_secs(10));
+        assert_eq!(scheduler.timer_count(), 3);
+        parent.cancel();
+        assert!(grandchild.wait_timeout(Duration::from_secs(1)));
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert_eq!(scheduler.timer_count(), 0);
+
+        let orphan = parent.child(Duration::from_secs(1));
+        assert!(orphan.is_expired() && orphan.is_cancelled());
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Sender;
+
+use crate::{Builder, Scheduler, TickReceiver, TimerId};
+
+/// The finest default tick of the time wheel behind a `Debouncer` or a `Throttler`.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Default tick for timers of `span`, a hundredth of it.
+fn default_interval(span: Duration) -> Duration {
+    (span / 100).max(MIN_INTERVAL)
+}
+
+/// Called with the states locked when the timer of a key expires.
+type Expire<K, S, T> = fn(&Gate<K, S, T>, &mut HashMap<K, S>, TimerId, K);
+
+/// Per key states with one timer per key, shared with the thread handling the expired timers.
+struct Gate<K, S, T> {
+    scheduler: Scheduler<K, K>,
+    states: Mutex<HashMap<K, S>>,
+    output: Sender<(TimerId, (K, T))>,
+}
+
+impl<K, S, T> Gate<K, S, T>
+where
+    K: Hash + Eq + Clone + Debug + Send + 'static,
+    S: Send + 'static,
+    T: Send + 'static,
+{
+    /// Start the gate on a wheel of the tick `interval`, with the thread calling `expire`.
+    fn start(
+        name: &str,
+        interval: Duration,
+        expire: Expire<K, S, T>,
+    ) -> (Arc<Self>, TickReceiver<(K, T)>) {
+        let (scheduler, expirations) = Builder::new(interval).workers(1).build_keyed::<K, K>();
+        let (output, receiver) = crossbeam_channel::unbounded();
+        let gate = Arc::new(Gate {
+            scheduler,
+            states: Mutex::new(HashMap::new()),
+            output,
+        });
+
+        let weak = Arc::downgrade(&gate);
+        thread::Builder::new()
+            .name(format!("xpd-timer-{}", name))
+            .spawn(move || Self::run(expirations, weak, expire))
+            .expect("failed to spawn timer gate thread");
+
+        (gate, TickReceiver::new(receiver, None))
+    }
+
+    /// Handle the expired timers until the gate is dropped.
+    fn run(expirations: TickReceiver<K>, gate: Weak<Self>, expire: Expire<K, S, T>) {
+        while let Ok((id, key)) = expirations.recv_with_id() {
+            let gate = match gate.upgrade() {
+                Some(gate) => gate,
+                None => return,
+            };
+            let mut states = gate.states.lock().unwrap();
+            expire(&gate, &mut states, id, key);
+        }
+    }
+
+    /// Arrange the timer of `key` at `when`, replacing the pending one.
+    fn arm(&self, key: &K, when: SystemTime) -> TimerId {
+        self.scheduler
+            .arrange_keyed(key.clone(), key.clone())
+            .at(when)
+    }
+
+    fn emit(&self, id: TimerId, key: K, payload: T) {
+        let _ = self.output.send((id, (key, payload)));
+    }
+
+    /// Drop the state and the timer of `key`.
+    fn cancel(&self, key: &K) -> bool {
+        let mut states = self.states.lock().unwrap();
+        if states.remove(key).is_none() {
+            return false;
+        }
+        self.scheduler.cancel_key(key);
+        true
+    }
+}
+
+impl<K, S, T> Drop for Gate<K, S, T> {
+    fn drop(&mut self) {
+        // the thread handling the expired timers exits once the timer thread does
+        self.scheduler.shutdown();
+    }
+}
+
+struct Burst<T> {
+    id: TimerId,
+    started: SystemTime,
+    latest: Option<T>,
+}
+
+/// Emits the latest payload of a key once it is quiet for `wait`.
+///
+/// A burst of calls of a key emits on the trailing edge by default, on the leading edge too with `leading`,
+/// and `max_wait` bounds how long a busy key is held back.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::Debouncer;
+/// use std::time::Duration;
+///
+/// let (debouncer, receiver) = Debouncer::new(Duration::from_millis(20));
+/// debouncer.call("search", "r");
+/// debouncer.call("search", "ru");
+/// debouncer.call("search", "rust");
+///
+/// assert_eq!(receiver.recv().unwrap(), ("search", "rust"));
+/// ```
+pub struct Debouncer<K, T> {
+    gate: Arc<Gate<K, Burst<T>, T>>,
+    wait: Duration,
+    max_wait: Option<Duration>,
+    leading: bool,
+    trailing: bool,
+}
+
+impl<K, T> Debouncer<K, T>
+where
+    K: Hash + Eq + Clone + Debug + Send + 'static,
+    T: Send + 'static,
+{
+    /// New debouncer emitting after `wait` of quiet, return it and the receiver of the emitted payloads.
+    ///
+    /// The timers tick at a hundredth of `wait`, but no faster than every millisecond.
+    pub fn new(wait: Duration) -> (Self, TickReceiver<(K, T)>) {
+        Self::with_interval(wait, default_interval(wait))
+    }
+
+    /// New debouncer with timers of the tick `interval`, the deadlines are rounded to it.
+    pub fn with_interval(wait: Duration, interval: Duration) -> (Self, TickReceiver<(K, T)>) {
+        let (gate, receiver) = Gate::start("debouncer", interval, Self::expire);
+        let debouncer = Debouncer {
+            gate,
+            wait,
+            max_wait: None,
+            leading: false,
+            trailing: true,
+        };
+        (debouncer, receiver)
+    }
+
+    fn expire(gate: &Gate<K, Burst<T>, T>, bursts: &mut HashMap<K, Burst<T>>, id: TimerId, key: K) {
+        match bursts.get(&key) {
+            Some(burst) if burst.id == id => {}
+            // the timer of an older burst
+            _ => return,
+        }
+        if let Some(latest) = bursts.remove(&key).and_then(|burst| burst.latest) {
+            gate.emit(id, key, latest);
+        }
+    }
+
+    /// Emit the first payload of a burst right away, default is `false`.
+    pub fn leading(mut self, leading: bool) -> Self {
+        self.leading = leading;
+        self
+    }
+
+    /// Emit the latest payload when a burst ends, default is `true`.
+    pub fn trailing(mut self, trailing: bool) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// End a burst at most `max_wait` after it starts, even if the calls keep coming.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Call with a payload of `key`, it replaces the pending one.
+    pub fn call(&self, key: K, payload: T) {
+        let now = SystemTime::now();
+        let mut bursts = self.gate.states.lock().unwrap();
+
+        let started = match bursts.get(&key) {
+            Some(burst) => burst.started,
+            None => now,
+        };
+        let mut when = now + self.wait;
+        if let Some(max_wait) = self.max_wait {
+            when = when.min(started + max_wait);
+        }
+        let id = self.gate.arm(&key, when);
+
+        if !bursts.contains_key(&key) && self.leading {
+            bursts.insert(
+                key.clone(),
+                Burst {
+                    id,
+                    started,
+                    latest: None,
+                },
+            );
+            self.gate.emit(id, key, payload);
+            return;
+        }
+
+        let latest = self.trailing.then_some(payload);
+        bursts.insert(
+            key,
+            Burst {
+                id,
+                started,
+                latest,
+            },
+        );
+    }
+
+    /// Drop the pending payload of `key`, return `false` if there is none.
+    pub fn cancel(&self, key: &K) -> bool {
+        self.gate.cancel(key)
+    }
+}
+
+struct Window<T> {
+    id: TimerId,
+    period: Duration,
+    latest: Option<T>,
+}
+
+/// Emits the payloads of a key at most once per `period`.
+///
+/// The first call of a key is emitted right away and the latest call within a period
+/// when the period ends, either edge can be turned off.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::Throttler;
+/// use std::time::Duration;
+///
+/// let (throttler, receiver) = Throttler::new(Duration::from_millis(20));
+/// for progress in 1..=100 {
+///     throttler.call("upload", progress);
+/// }
+///
+/// assert_eq!(receiver.recv().unwrap(), ("upload", 1));
+/// assert_eq!(receiver.recv().unwrap(), ("upload", 100));
+/// ```
+pub struct Throttler<K, T> {
+    gate: Arc<Gate<K, Window<T>, T>>,
+    period: Duration,
+    leading: bool,
+    trailing: bool,
+}
+
+impl<K, T> Throttler<K, T>
+where
+    K: Hash + Eq + Clone + Debug + Send + 'static,
+    T: Send + 'static,
+{
+    /// New throttler emitting at most once per `period`, return it and the receiver of the emitted payloads.
+    ///
+    /// The timers tick at a hundredth of `period`, but no faster than every millisecond.
+    pub fn new(period: Duration) -> (Self, TickReceiver<(K, T)>) {
+        Self::with_interval(period, default_interval(period))
+    }
+
+    /// New throttler with timers of the tick `interval`, the deadlines are rounded to it.
+    pub fn with_interval(period: Duration, interval: Duration) -> (Self, TickReceiver<(K, T)>) {
+        let (gate, receiver) = Gate::start("throttler", interval, Self::expire);
+        let throttler = Throttler {
+            gate,
+            period,
+            leading: true,
+            trailing: true,
+        };
+        (throttler, receiver)
+    }
+
+    fn expire(
+        gate: &Gate<K, Window<T>, T>,
+        windows: &mut HashMap<K, Window<T>>,
+        id: TimerId,
+        key: K,
+    ) {
+        let window = match windows.get_mut(&key) {
+            Some(window) if window.id == id => window,
+            // the timer of an older window
+            _ => return,
+        };
+        match window.latest.take() {
+            Some(latest) => {
+                // emitting the trailing edge starts the next period
+                window.id = gate.arm(&key, SystemTime::now() + window.period);
+                gate.emit(id, key, latest);
+            }
+            None => {
+                windows.remove(&key);
+            }
+        }
+    }
+
+    /// Emit the first call of a key right away, default is `true`.
+    pub fn leading(mut self, leading: bool) -> Self {
+        self.leading = leading;
+        self
+    }
+
+    /// Emit the latest call within a period when it ends, default is `true`.
+    pub fn trailing(mut self, trailing: bool) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// Call with a payload of `key`, it is emitted now, at the end of the period, or dropped.
+    pub fn call(&self, key: K, payload: T) {
+        let mut windows = self.gate.states.lock().unwrap();
+
+        if let Some(window) = windows.get_mut(&key) {
+            if self.trailing {
+                window.latest = Some(payload);
+            }
+            return;
+        }
+
+        let id = self.gate.arm(&key, SystemTime::now() + self.period);
+        let (leading, latest) = match (self.leading, self.trailing) {
+            (true, _) => (Some(payload), None),
+            (false, true) => (None, Some(payload)),
+            (false, false) => (None, None),
+        };
+        windows.insert(
+            key.clone(),
+            Window {
+                id,
+                period: self.period,
+                latest,
+            },
+        );
+        if let Some(payload) = leading {
+            self.gate.emit(id, key, payload);
+        }
+    }
+
+    /// Drop the pending payload and the period of `key`, return `false` if there is none.
+    pub fn cancel(&self, key: &K) -> bool {
+        self.gate.cancel(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_debounce() {
+        let (debouncer, receiver) = Debouncer::new(Duration::from_millis(30));
+        for i in 0..5 {
+            debouncer.call("a", i);
+            debouncer.call("b", i * 10);
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(debouncer.cancel(&"b"));
+        assert!(!debouncer.cancel(&"b"));
+        assert_eq!(receiver.recv().unwrap(), ("a", 4));
+        // nothing of the cancelled key comes before a later one
+        debouncer.call("c", 99);
+        assert_eq!(receiver.recv().unwrap(), ("c", 99));
+        // the pending payload goes with the debouncer
+        debouncer.call("d", 100);
+        drop(debouncer);
+        assert!(receiver.recv().is_err());
+
+        let (debouncer, receiver) = Debouncer::new(Duration::from_millis(30));
+        let debouncer = debouncer.leading(true).trailing(false);
+        debouncer.call("a", 1);
+        debouncer.call("a", 2);
+        assert_eq!(receiver.recv().unwrap(), ("a", 1));
+        // the burst ends with its timer, however late the timer thread is
+        while debouncer.gate.states.lock().unwrap().contains_key("a") {
+            thread::sleep(Duration::from_millis(5));
+        }
+        debouncer.call("a", 3);
+        assert_eq!(receiver.recv().unwrap(), ("a", 3));
+    }
+
+    #[test]
+    fn test_debounce_max_wait() {
+        let (wait, max_wait) = (Duration::from_millis(500), Duration::from_millis(100));
+        let (debouncer, receiver) = Debouncer::new(wait);
+        let debouncer = debouncer.max_wait(max_wait);
+        let calls = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            // never quiet for the wait
+            scope.spawn(|| {
+                for i in 0..20 {
+                    calls.lock().unwrap().push(Instant::now());
+                    debouncer.call("busy", i);
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
+
+            // flushed by the max wait from the first call, the trailing edge is a wait after the last one
+            let (_, first) = receiver.recv().unwrap();
+            let flushed = Instant::now();
+            let start = calls.lock().unwrap()[0];
+            assert!(flushed >= start + max_wait);
+            assert!(flushed < start + wait, "{:?}", flushed - start);
+            assert!(first < 19, "{}", first);
+
+            // then the next bursts, in order
+            let mut last = first;
+            while last < 19 {
+                let (_, next) = receiver.recv().unwrap();
+                assert!(next > last);
+                last = next;
+            }
+        });
+    }
+
+    #[test]
+    fn test_throttle() {
+        let (throttler, receiver) = Throttler::new(Duration::from_millis(40));
+        for i in 0..5 {
+            throttler.call("a", i);
+        }
+        assert_eq!(receiver.recv().unwrap(), ("a", 0));
+        assert_eq!(receiver.recv().unwrap(), ("a", 4));
+        // still within the period started by the trailing edge
+        throttler.call("a", 5);
+        throttler.call("a", 6);
+        assert_eq!(receiver.recv().unwrap(), ("a", 6));
+
+        let (throttler, receiver) =
+            Throttler::with_interval(Duration::from_millis(20), Duration::from_millis(1));
+        let throttler = throttler.trailing(false);
+        throttler.call("a", 1);
+        throttler.call("a", 2);
+        assert_eq!(receiver.recv().unwrap(), ("a", 1));
+        // the period ends with its timer, however late the timer thread is
+        while throttler.gate.states.lock().unwrap().contains_key("a") {
+            thread::sleep(Duration::from_millis(5));
+        }
+        throttler.call("a", 3);
+        assert_eq!(receiver.recv().unwrap(), ("a", 3));
+    }
+}
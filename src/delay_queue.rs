@@ -0,0 +1,320 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+
+use crate::core::{Clock, Entity, Wheel};
+use crate::Rounding;
+
+/// Identifies an entry of a `DelayQueue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(u64);
+
+/// An entry expired from a `DelayQueue`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Expired<T> {
+    pub key: Key,
+    /// The deadline it is inserted with, the entry expires no earlier than it.
+    pub deadline: SystemTime,
+    pub value: T,
+}
+
+/// Entries noticed by the wheel, waiting to be polled.
+type Noticed<T> = Rc<RefCell<VecDeque<Expired<T>>>>;
+
+/// A queue of values that expire after their deadlines, on a hierarchical wheel.
+///
+/// There is no timer thread, the wheel only moves when `poll_expired` is called,
+/// which makes it a container for single-threaded components.
+/// Entries never expire before their deadlines, and expire in the order of their deadlines.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::DelayQueue;
+/// use std::time::{Duration, SystemTime};
+///
+/// let mut queue = DelayQueue::new();
+/// let key = queue.insert("session", Duration::from_secs(30));
+///
+/// assert!(queue.poll_expired(SystemTime::now()).is_none());
+/// let expired = queue.poll_expired(SystemTime::now() + Duration::from_secs(31)).unwrap();
+/// assert_eq!(expired.key, key);
+/// assert_eq!(expired.value, "session");
+/// ```
+pub struct DelayQueue<T> {
+    wheel: Wheel<T>,
+    /// Deadlines of the entries on the wheel with their keys, the earliest first.
+    deadlines_on_wheel: BTreeSet<(SystemTime, u64)>,
+    /// Rounds a deadline up to the tick it expires in.
+    deadlines: Clock,
+    /// Rounds `now` down to the tick it is in.
+    ticks: Clock,
+    noticed: Noticed<T>,
+    /// Entries expired but not polled yet, in the order of the deadlines,
+    /// earlier than the ones on the wheel.
+    expired: VecDeque<Expired<T>>,
+    sequence: u64,
+}
+
+impl<T: 'static> DelayQueue<T> {
+    /// New queue with a resolution of 1 millisecond.
+    pub fn new() -> Self {
+        Self::with_interval(Duration::from_millis(1))
+    }
+
+    /// New queue with a resolution of `interval`, the deadlines are rounded up to it.
+    pub fn with_interval(interval: Duration) -> Self {
+        let start_at = SystemTime::now();
+        let noticed = Noticed::default();
+
+        let notice = {
+            let noticed = noticed.clone();
+            move |entity: Entity<T>| {
+                noticed.borrow_mut().push_back(Expired {
+                    key: Key(entity.id),
+                    deadline: entity.when,
+                    value: entity.data,
+                })
+            }
+        };
+
+        DelayQueue {
+            wheel: Wheel::new(notice),
+            deadlines_on_wheel: BTreeSet::new(),
+            deadlines: Clock::new(start_at, interval, Rounding::NeverEarly),
+            ticks: Clock::new(start_at, interval, Rounding::NeverLate),
+            noticed,
+            expired: VecDeque::new(),
+            sequence: 0,
+        }
+    }
+}
+
+impl<T: 'static> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> DelayQueue<T> {
+    /// Insert a value expiring after `delay`.
+    pub fn insert(&mut self, value: T, delay: Duration) -> Key {
+        self.insert_at(value, SystemTime::now() + delay)
+    }
+
+    /// Insert a value expiring at `deadline`.
+    pub fn insert_at(&mut self, value: T, deadline: SystemTime) -> Key {
+        self.sequence += 1;
+        self.schedule(self.sequence, value, deadline);
+        Key(self.sequence)
+    }
+
+    fn schedule(&mut self, id: u64, value: T, deadline: SystemTime) {
+        let offset = self
+            .deadlines
+            .tick_of(deadline)
+            .saturating_sub(self.wheel.ticks);
+        if offset > 0 {
+            self.wheel.schedule(id, value, offset, deadline, 0);
+            self.deadlines_on_wheel.insert((deadline, id));
+            return;
+        }
+
+        // expired already, it takes its place among the ones not polled yet
+        let position = self
+            .expired
+            .partition_point(|expired| (expired.deadline, expired.key.0) < (deadline, id));
+        let expired = Expired {
+            key: Key(id),
+            deadline,
+            value,
+        };
+        self.expired.insert(position, expired);
+    }
+
+    /// Remove an entry, expired or not, `None` if it has been polled or removed.
+    pub fn remove(&mut self, key: &Key) -> Option<T> {
+        if let Some(entity) = self.wheel.cancel(key.0) {
+            self.deadlines_on_wheel.remove(&(entity.when, entity.id));
+            return Some(entity.data);
+        }
+
+        let position = self
+            .expired
+            .iter()
+            .position(|expired| expired.key == *key)?;
+        self.expired.remove(position).map(|expired| expired.value)
+    }
+
+    /// Move an entry to expire after `delay`, return `false` if it has been polled or removed.
+    pub fn reset(&mut self, key: &Key, delay: Duration) -> bool {
+        self.reset_at(key, SystemTime::now() + delay)
+    }
+
+    /// Move an entry to expire at `deadline`, return `false` if it has been polled or removed.
+    ///
+    /// The entry keeps its key, among the entries with the same deadline it expires in the order it is inserted.
+    pub fn reset_at(&mut self, key: &Key, deadline: SystemTime) -> bool {
+        match self.remove(key) {
+            Some(value) => {
+                self.schedule(key.0, value, deadline);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take the next entry expired at `now`, in the order of the deadlines.
+    ///
+    /// The entries expired at a later `now` are kept until `now` reaches them again.
+    pub fn poll_expired(&mut self, now: SystemTime) -> Option<Expired<T>> {
+        let ticks = self.ticks.tick_of(now);
+        if self.expired.is_empty() {
+            self.advance(ticks);
+        }
+
+        let deadline = self.expired.front()?.deadline;
+        if self.deadlines.tick_of(deadline) > ticks {
+            return None;
+        }
+        self.expired.pop_front()
+    }
+
+    fn advance(&mut self, ticks: u64) {
+        // the wheel moves at most `u32::MAX` ticks at a time
+        const MAX_STEP: u64 = 1 << 30;
+
+        while self.wheel.ticks + MAX_STEP < ticks {
+            self.wheel.tick_to(self.wheel.ticks + MAX_STEP);
+        }
+        self.wheel.tick_to(ticks);
+        for expired in self.noticed.borrow_mut().drain(..) {
+            self.deadlines_on_wheel
+                .remove(&(expired.deadline, expired.key.0));
+            self.expired.push_back(expired);
+        }
+    }
+
+    /// The earliest deadline of the entries, including the expired ones not polled yet.
+    pub fn peek_deadline(&self) -> Option<SystemTime> {
+        let expired = self.expired.front().map(|expired| expired.deadline);
+        let on_wheel = self
+            .deadlines_on_wheel
+            .first()
+            .map(|(deadline, _)| *deadline);
+        expired.into_iter().chain(on_wheel).min()
+    }
+
+    /// Number of entries, including the expired ones not polled yet.
+    pub fn len(&self) -> usize {
+        self.wheel.len() + self.expired.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Visit the entries with their deadlines, including the expired ones not polled yet, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, SystemTime, &T)> {
+        let expired = self
+            .expired
+            .iter()
+            .map(|expired| (expired.key, expired.deadline, &expired.value));
+        self.wheel
+            .entities()
+            .map(|entity| (Key(entity.id), entity.when, &entity.data))
+            .chain(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_expired() {
+        let mut queue = DelayQueue::with_interval(Duration::from_millis(10));
+        let now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+
+        let late = queue.insert_at("late", at(5_000_000));
+        let second = queue.insert_at("second", at(25));
+        let first = queue.insert_at("first", at(24));
+        queue.insert_at("third", at(25));
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.peek_deadline(), Some(at(24)));
+
+        assert!(queue.poll_expired(at(20)).is_none());
+        let expired = queue.poll_expired(at(30)).unwrap();
+        assert_eq!((expired.key, expired.value), (first, "first"));
+        assert_eq!(expired.deadline, at(24));
+        assert_eq!(queue.poll_expired(at(30)).unwrap().key, second);
+        assert_eq!(queue.poll_expired(at(30)).unwrap().value, "third");
+        assert!(queue.poll_expired(at(30)).is_none());
+
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.poll_expired(at(5_000_010)).unwrap().key, late);
+        assert!(queue.is_empty());
+        assert_eq!(queue.peek_deadline(), None);
+    }
+
+    #[test]
+    fn test_poll_in_order() {
+        let mut queue = DelayQueue::with_interval(Duration::from_millis(10));
+        let now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+
+        queue.insert_at("a", at(20));
+        queue.insert_at("c", at(60));
+        assert_eq!(queue.poll_expired(at(70)).unwrap().value, "a");
+        // expired at a later `now`, not at an earlier one
+        assert!(queue.poll_expired(at(30)).is_none());
+        assert_eq!(queue.peek_deadline(), Some(at(60)));
+
+        // inserted in the past, before the ones expired earlier
+        queue.insert_at("b", at(40));
+        assert_eq!(queue.peek_deadline(), Some(at(40)));
+        assert_eq!(queue.poll_expired(at(50)).unwrap().value, "b");
+        assert!(queue.poll_expired(at(50)).is_none());
+        assert_eq!(queue.poll_expired(at(70)).unwrap().value, "c");
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_remove_and_reset() {
+        let mut queue = DelayQueue::new();
+        let now = SystemTime::now();
+        let at = |secs| now + Duration::from_secs(secs);
+
+        let a = queue.insert_at(1, at(10));
+        let b = queue.insert_at(2, at(20));
+        let c = queue.insert_at(3, at(30));
+
+        assert_eq!(queue.remove(&b), Some(2));
+        assert_eq!(queue.remove(&b), None);
+        assert!(queue.reset_at(&a, at(40)));
+
+        let mut entries = queue
+            .iter()
+            .map(|(key, _, value)| (key, *value))
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, vec![(a, 1), (c, 3)]);
+
+        assert_eq!(queue.poll_expired(at(35)).unwrap().key, c);
+        assert!(queue.poll_expired(at(25)).is_none());
+        queue.insert_at(4, at(1));
+        assert_eq!(queue.peek_deadline(), Some(at(1)));
+        assert_eq!(queue.poll_expired(at(45)).unwrap().value, 4);
+        let entries = queue.iter().map(|(key, ..)| key).collect::<Vec<_>>();
+        assert_eq!(entries, vec![a]);
+        assert_eq!(queue.len(), 1);
+        // an expired entry not polled yet can still be removed
+        assert_eq!(queue.remove(&a), Some(1));
+        assert!(!queue.reset(&a, Duration::ZERO));
+        assert!(queue.is_empty());
+    }
+}
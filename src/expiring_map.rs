@@ -0,0 +1,351 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Sender;
+
+use crate::{Builder, Scheduler, TickReceiver, TimerId};
+
+struct Entry<V> {
+    value: V,
+    ttl: Duration,
+    expires_at: SystemTime,
+    /// The timer of the entry, an expired timer of an older one is ignored.
+    id: TimerId,
+    /// Position in the LRU order.
+    used: u64,
+}
+
+struct Entries<K, V> {
+    map: HashMap<K, Entry<V>>,
+    /// Keys by the last use, only kept with a capacity.
+    lru: BTreeMap<u64, K>,
+    uses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Entries<K, V> {
+    fn remove(&mut self, key: &K) -> Option<Entry<V>> {
+        let entry = self.map.remove(key)?;
+        self.lru.remove(&entry.used);
+        Some(entry)
+    }
+}
+
+/// A key/value map whose entries expire after their TTLs.
+///
+/// Expirations are driven by a time wheel, the expired entries are evicted without scanning
+/// and sent to the receiver returned by `new`, together with the ones evicted by the capacity.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::ExpiringMap;
+/// use std::time::Duration;
+///
+/// let (map, evictions) = ExpiringMap::new(Duration::from_millis(1));
+/// map.insert("session", 42, Duration::from_millis(10));
+/// assert_eq!(map.get(&"session"), Some(42));
+///
+/// assert_eq!(evictions.recv().unwrap(), ("session", 42));
+/// assert_eq!(map.get(&"session"), None);
+/// ```
+pub struct ExpiringMap<K, V> {
+    entries: Arc<Mutex<Entries<K, V>>>,
+    scheduler: Scheduler<K, K>,
+    evictions: Sender<(TimerId, (K, V))>,
+    sliding: bool,
+    capacity: Option<usize>,
+}
+
+impl<K, V> ExpiringMap<K, V>
+where
+    K: Hash + Eq + Clone + Debug + Send + 'static,
+    V: Send + 'static,
+{
+    /// New map with a time wheel of the tick `interval`, return the map and the receiver of the evicted entries.
+    pub fn new(interval: Duration) -> (Self, TickReceiver<(K, V)>) {
+        let (scheduler, expirations) = Builder::new(interval).workers(1).build_keyed::<K, K>();
+        let (evictions, receiver) = crossbeam_channel::unbounded();
+        let entries = Arc::new(Mutex::new(Entries {
+            map: HashMap::new(),
+            lru: BTreeMap::new(),
+            uses: 0,
+        }));
+
+        let weak = Arc::downgrade(&entries);
+        let sender = evictions.clone();
+        thread::Builder::new()
+            .name("xpd-timer-expiring-map".to_string())
+            .spawn(move || evict_expired(expirations, weak, sender))
+            .expect("failed to spawn expiring map thread");
+
+        let map = ExpiringMap {
+            entries,
+            scheduler,
+            evictions,
+            sliding: false,
+            capacity: None,
+        };
+        (map, TickReceiver::new(receiver, None))
+    }
+}
+
+impl<K, V> ExpiringMap<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Refresh the TTL of an entry on every `get`, default is a fixed expiry from the insertion.
+    pub fn sliding(mut self) -> Self {
+        self.sliding = true;
+        self
+    }
+
+    /// Keep at most `capacity` entries, inserting a new key evicts the least recently used one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn max_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "the capacity of an expiring map is zero");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Insert an entry expiring after `ttl`, return the previous value of `key`.
+    pub fn insert(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let previous = entries.remove(&key);
+        if previous.is_none() && self.capacity == Some(entries.map.len()) {
+            self.evict_least_recently_used(&mut entries);
+        }
+
+        let id = self.arm(&key, ttl);
+        let used = self.touch(&mut entries, &key);
+        entries.map.insert(
+            key,
+            Entry {
+                value,
+                ttl,
+                expires_at: SystemTime::now() + ttl,
+                id,
+                used,
+            },
+        );
+        previous.map(|entry| entry.value)
+    }
+
+    /// The value of `key`, also refreshing its TTL if the map is `sliding`.
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.lookup(key, self.sliding)
+    }
+
+    /// The value of `key`, refreshing its TTL.
+    pub fn get_and_refresh(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.lookup(key, true)
+    }
+
+    fn lookup(&self, key: &K, refresh: bool) -> Option<V>
+    where
+        V: Clone,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.map.get(key)?;
+        // expired, but not evicted yet
+        if entry.expires_at <= SystemTime::now() {
+            return None;
+        }
+        let (ttl, used) = (entry.ttl, entry.used);
+
+        let id = refresh.then(|| self.arm(key, ttl));
+        let used = match self.capacity {
+            Some(_) => {
+                entries.lru.remove(&used);
+                self.touch(&mut entries, key)
+            }
+            None => used,
+        };
+
+        let entry = entries.map.get_mut(key)?;
+        if let Some(id) = id {
+            entry.id = id;
+            entry.expires_at = SystemTime::now() + ttl;
+        }
+        entry.used = used;
+        Some(entry.value.clone())
+    }
+
+    /// Remove an entry before it expires.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let entry = self.entries.lock().unwrap().remove(key)?;
+        // by the id, the timer of the key inserted again in between is kept
+        self.scheduler.discard(entry.id);
+        Some(entry.value)
+    }
+
+    /// Remove all entries, return them without sending them to the evictions.
+    pub fn purge(&self) -> Vec<(K, V)> {
+        let drained = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.lru.clear();
+            entries.map.drain().collect::<Vec<_>>()
+        };
+        drained
+            .into_iter()
+            .map(|(key, entry)| {
+                self.scheduler.discard(entry.id);
+                (key, entry.value)
+            })
+            .collect()
+    }
+
+    /// Number of entries, including the expired ones not evicted yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Arrange the expiry of `key`, replacing the pending one.
+    fn arm(&self, key: &K, ttl: Duration) -> TimerId {
+        self.scheduler
+            .arrange_keyed(key.clone(), key.clone())
+            .after(ttl)
+    }
+
+    /// Mark `key` as the most recently used.
+    fn touch(&self, entries: &mut Entries<K, V>, key: &K) -> u64 {
+        entries.uses += 1;
+        if self.capacity.is_some() {
+            entries.lru.insert(entries.uses, key.clone());
+        }
+        entries.uses
+    }
+
+    fn evict_least_recently_used(&self, entries: &mut Entries<K, V>) {
+        let key = match entries.lru.values().next() {
+            Some(key) => key.clone(),
+            None => return,
+        };
+        if let Some(entry) = entries.remove(&key) {
+            self.scheduler.discard(entry.id);
+            let _ = self.evictions.send((entry.id, (key, entry.value)));
+        }
+    }
+}
+
+impl<K, V> Drop for ExpiringMap<K, V> {
+    fn drop(&mut self) {
+        // the eviction thread exits once the timer thread does
+        self.scheduler.shutdown();
+    }
+}
+
+/// Evict the entries of the expired timers, until the map is dropped.
+fn evict_expired<K: Hash + Eq + Clone, V>(
+    expirations: TickReceiver<K>,
+    entries: Weak<Mutex<Entries<K, V>>>,
+    evictions: Sender<(TimerId, (K, V))>,
+) {
+    while let Ok((id, key)) = expirations.recv_with_id() {
+        let entries = match entries.upgrade() {
+            Some(entries) => entries,
+            None => return,
+        };
+        let mut entries = entries.lock().unwrap();
+        // the key may be refreshed or inserted again after this timer expired
+        if entries.map.get(&key).is_some_and(|entry| entry.id == id) {
+            let entry = entries.remove(&key).unwrap();
+            let _ = evictions.send((id, (key, entry.value)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry() {
+        let (map, evictions) = ExpiringMap::new(Duration::from_millis(1));
+
+        assert_eq!(map.insert("a", 1, Duration::from_millis(20)), None);
+        assert_eq!(map.insert("a", 2, Duration::from_millis(30)), Some(1));
+        map.insert("b", 3, Duration::from_millis(10));
+        map.insert("c", 4, Duration::from_secs(60));
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(evictions.recv().unwrap(), ("b", 3));
+        assert_eq!(evictions.recv().unwrap(), ("a", 2));
+        assert_eq!(map.get(&"a"), None);
+        assert_eq!(map.get(&"c"), Some(4));
+
+        assert_eq!(map.remove(&"c"), Some(4));
+        assert!(map.is_empty());
+        // the timer of the removed entry goes, not the one of the key inserted again
+        map.insert("c", 5, Duration::from_millis(10));
+        assert_eq!(evictions.recv().unwrap(), ("c", 5));
+
+        // the wheel is shut down with the map, not kept alive by a pending timer
+        map.insert("d", 6, Duration::from_secs(60));
+        drop(map);
+        assert!(evictions.recv().is_err());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let (map, evictions) = ExpiringMap::new(Duration::from_millis(1));
+        let map = map.sliding();
+
+        map.insert("sliding", 1, Duration::from_millis(100));
+        map.insert("fixed", 2, Duration::from_millis(150));
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(30));
+            assert_eq!(map.get(&"sliding"), Some(1));
+        }
+        // longer than its TTL since it was inserted, kept alive by the reads
+        assert_eq!(evictions.recv().unwrap(), ("fixed", 2));
+        assert_eq!(evictions.recv().unwrap(), ("sliding", 1));
+
+        let (map, evictions) = ExpiringMap::new(Duration::from_millis(1));
+        map.insert("refreshed", 1, Duration::from_millis(100));
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(map.get_and_refresh(&"refreshed"), Some(1));
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(map.get(&"refreshed"), Some(1));
+        assert_eq!(evictions.recv().unwrap(), ("refreshed", 1));
+    }
+
+    #[test]
+    fn test_capacity_and_purge() {
+        let (map, evictions) = ExpiringMap::new(Duration::from_millis(1));
+        let map = map.max_capacity(2);
+        let ttl = Duration::from_secs(60);
+
+        map.insert(1, "one", ttl);
+        map.insert(2, "two", ttl);
+        assert_eq!(map.get(&1), Some("one"));
+        map.insert(3, "three", ttl);
+        assert_eq!(evictions.recv().unwrap(), (2, "two"));
+        assert_eq!(map.len(), 2);
+
+        let mut purged = map.purge();
+        purged.sort();
+        assert_eq!(purged, vec![(1, "one"), (3, "three")]);
+        assert!(map.is_empty());
+        assert_eq!(map.scheduler.pending_count(), 0);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::time_wheel::Alarm;
+#[cfg(feature = "async")]
+use crate::Sleep;
+
+/// What an `Interval` does when ticks are missed, e.g. the consumer is too slow.
+///
+/// A tick a bit late is not missed, it is missed only once the tick after it is due too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// Yield the missed ticks right away until it catches up, the phase is kept.
+    #[default]
+    Burst,
+    /// Yield one tick now and start over from it, the phase moves.
+    Delay,
+    /// Yield one tick and skip the rest of the missed ones, the phase is kept.
+    Skip,
+}
+
+/// Ticks every `period`, created by `Scheduler::interval`.
+///
+/// The `n`th tick is at `start + n * period`, computed from the start rather than from the previous tick,
+/// so it does not drift however long it runs. The phase is kept on the ticks of the wheel, which are counted
+/// on the monotonic clock, so a step of the system time neither shifts nor bunches the ticks.
+/// It is a blocking iterator of the tick instants, ended if the timer thread exits,
+/// and a `Stream` of them with the `async` feature.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::time_wheel;
+/// use std::time::Duration;
+///
+/// let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+/// let mut interval = scheduler.interval(Duration::from_millis(10));
+///
+/// let first = interval.next().unwrap();
+/// let second = interval.next().unwrap();
+/// assert_eq!(second.duration_since(first).unwrap(), Duration::from_millis(10));
+/// ```
+pub struct Interval {
+    alarm: Arc<dyn Alarm>,
+    /// Where the phase starts on the wheel, tick `n` is `anchor + n * period` after the start of the wheel.
+    anchor: Duration,
+    /// The index of the next tick.
+    n: u64,
+    period: Duration,
+    behavior: MissedTickBehavior,
+    /// The sleep of the next tick, of the stream.
+    #[cfg(feature = "async")]
+    sleep: Option<Sleep>,
+}
+
+impl Interval {
+    pub(crate) fn new(alarm: Arc<dyn Alarm>, period: Duration) -> Self {
+        assert!(!period.is_zero(), "the period of an interval is zero");
+        Interval {
+            anchor: alarm.elapsed(),
+            alarm,
+            n: 1,
+            period,
+            behavior: MissedTickBehavior::default(),
+            #[cfg(feature = "async")]
+            sleep: None,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.behavior = behavior;
+    }
+
+    /// Change the period, the next tick is one new period after the last one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn set_period(&mut self, period: Duration) {
+        assert!(!period.is_zero(), "the period of an interval is zero");
+        self.anchor = self.tick_at(self.n - 1);
+        self.n = 1;
+        self.period = period;
+    }
+
+    /// Start over from now, the next tick is one period later.
+    pub fn reset(&mut self) {
+        self.anchor = self.alarm.elapsed();
+        self.n = 1;
+    }
+
+    /// When the next tick is due.
+    pub fn next_tick(&self) -> SystemTime {
+        self.alarm.time_of(self.tick_at(self.n))
+    }
+
+    /// Time of the tick `n` on the wheel.
+    fn tick_at(&self, n: u64) -> Duration {
+        let nanos = self.anchor.as_nanos() + self.period.as_nanos() * n as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    /// The first tick of the wheel not earlier than the next tick, the timer of it expires
+    /// in the same tick whatever the rounding of the wheel.
+    fn wake_at(&self) -> SystemTime {
+        let interval = self.alarm.interval().as_nanos().max(1);
+        let nanos = self.tick_at(self.n).as_nanos().div_ceil(interval) * interval;
+        self.alarm
+            .time_of(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+    }
+
+    /// The next tick is reached at `now` on the wheel, return it and move to the tick after it.
+    fn fire(&mut self, now: Duration) -> SystemTime {
+        let due = self.tick_at(self.n);
+        let missed = now >= due + self.period;
+        match (missed, self.behavior) {
+            (false, _) | (true, MissedTickBehavior::Burst) => {
+                self.n += 1;
+                self.alarm.time_of(due)
+            }
+            (true, MissedTickBehavior::Delay) => {
+                self.anchor = now;
+                self.n = 1;
+                self.alarm.time_of(now)
+            }
+            (true, MissedTickBehavior::Skip) => {
+                let elapsed = now.saturating_sub(self.anchor);
+                self.n = (elapsed.as_nanos() / self.period.as_nanos()) as u64 + 1;
+                self.alarm.time_of(due)
+            }
+        }
+    }
+}
+
+impl Iterator for Interval {
+    type Item = SystemTime;
+
+    /// Block until the next tick, it ends only if the timer thread exits.
+    fn next(&mut self) -> Option<SystemTime> {
+        if self.alarm.elapsed() < self.tick_at(self.n) {
+            // woken right in the timer thread
+            let (wake, parked) = crossbeam_channel::bounded(1);
+            self.alarm.set(
+                self.wake_at(),
+                Box::new(move || {
+                    let _ = wake.send(());
+                }),
+            );
+            // the timer thread exited, the wheel does not move any more
+            parked.recv().ok()?;
+        }
+        Some(self.fire(self.alarm.elapsed()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for Interval {
+    type Item = SystemTime;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SystemTime>> {
+        let wake_at = self.wake_at();
+        let this = &mut *self;
+        // the tick is moved by `reset` or `set_period`
+        let sleep = match &mut this.sleep {
+            Some(sleep) if sleep.deadline() == wake_at => sleep,
+            sleep => sleep.insert(Sleep::new(this.alarm.clone(), wake_at)),
+        };
+        match Pin::new(sleep).poll(cx) {
+            Poll::Ready(()) => {
+                this.sleep = None;
+                Poll::Ready(Some(this.fire(this.alarm.elapsed())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_wheel;
+    use std::thread;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_phase() {
+        let (scheduler, _) = time_wheel::<()>(ms(1));
+        let mut interval = scheduler.interval(ms(10));
+        let start = interval.next_tick() - ms(10);
+
+        for n in 1..=5 {
+            let tick = interval.next().unwrap();
+            assert_eq!(tick, start + ms(10) * n);
+            assert!(SystemTime::now() >= tick);
+        }
+
+        interval.set_period(ms(20));
+        assert_eq!(interval.next().unwrap(), start + ms(70));
+
+        thread::sleep(ms(5));
+        interval.reset();
+        let reset = interval.next_tick();
+        assert!(reset >= start + ms(75) + ms(20));
+        assert_eq!(interval.next().unwrap(), reset);
+    }
+
+    #[test]
+    fn test_coarse_wheel() {
+        // the ticks of the interval are between the ticks of the wheel
+        let (scheduler, _) = time_wheel::<()>(ms(10));
+        let mut interval = scheduler.interval(ms(15));
+        let start = interval.next_tick() - ms(15);
+
+        for n in 1..=3 {
+            let tick = interval.next().unwrap();
+            assert_eq!(tick, start + ms(15) * n);
+            assert!(SystemTime::now() >= tick);
+        }
+    }
+
+    #[test]
+    fn test_missed_ticks() {
+        let (scheduler, _) = time_wheel::<()>(ms(1));
+
+        let mut interval = scheduler.interval(ms(10));
+        let start = interval.next_tick() - ms(10);
+        thread::sleep(ms(35));
+        // 3 missed ticks right away, then back in phase
+        let ticks = interval.by_ref().take(4).collect::<Vec<_>>();
+        assert_eq!(
+            ticks,
+            (1..=4).map(|n| start + ms(10) * n).collect::<Vec<_>>()
+        );
+
+        let mut interval = scheduler.interval(ms(10));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let start = interval.next_tick() - ms(10);
+        // the timer thread may run late, wait for the wheel itself
+        while interval.alarm.elapsed() < interval.tick_at(0) + ms(35) {
+            thread::sleep(ms(1));
+        }
+        assert_eq!(interval.next().unwrap(), start + ms(10));
+        let skipped = interval.next().unwrap();
+        assert!(skipped >= start + ms(40));
+        assert_eq!(skipped.duration_since(start).unwrap().as_millis() % 10, 0);
+
+        let mut interval = scheduler.interval(ms(10));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        thread::sleep(ms(35));
+        let late = interval.next().unwrap();
+        assert_eq!(interval.next_tick(), late + ms(10));
+        assert_eq!(interval.next().unwrap(), late + ms(10));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (scheduler, _) = time_wheel::<()>(ms(1));
+        let mut interval = scheduler.interval(ms(10));
+        assert!(interval.next().is_some());
+
+        // ended rather than ticking early once the timer thread is gone
+        scheduler.shutdown();
+        assert_eq!(interval.next(), None);
+        assert_eq!(interval.next(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_stream() {
+        use futures_core::Stream;
+        use std::{
+            pin::pin,
+            sync::Arc,
+            task::{Wake, Waker},
+            thread::Thread,
+        };
+
+        struct Unpark(Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let (scheduler, _) = time_wheel::<()>(ms(1));
+        let mut interval = pin!(scheduler.interval(ms(10)));
+        let start = interval.next_tick() - ms(10);
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        for n in 1..=3 {
+            let tick = loop {
+                match interval.as_mut().poll_next(&mut cx) {
+                    Poll::Ready(tick) => break tick.unwrap(),
+                    Poll::Pending => thread::park(),
+                }
+            };
+            assert_eq!(tick, start + ms(10) * n);
+        }
+    }
+}
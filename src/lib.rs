@@ -1,9 +1,35 @@
+mod admission;
 mod basic;
+mod builder;
 mod core;
+mod deadline_token;
+mod debounce;
+mod delay_queue;
+mod expiring_map;
+mod interval;
+mod pool;
+mod rate_limiter;
+mod retry;
+#[cfg(feature = "async")]
+mod sleep;
 mod time_wheel;
+mod watchdog;
 
 pub use crate::basic::*;
-pub use time_wheel::{time_wheel, Scheduler, TickReceiver};
+pub use admission::AdmissionMetrics;
+pub use builder::Builder;
+pub use deadline_token::DeadlineToken;
+pub use debounce::{Debouncer, Throttler};
+pub use delay_queue::{DelayQueue, Expired, Key};
+pub use expiring_map::ExpiringMap;
+pub use interval::{Interval, MissedTickBehavior};
+pub use pool::PoolMetrics;
+pub use rate_limiter::{KeyedRateLimiter, RateLimiter};
+pub use retry::{Retry, RetryScheduler};
+#[cfg(feature = "async")]
+pub use sleep::{Sleep, Timeout};
+pub use time_wheel::{time_wheel, InnerScheduler, Scheduler, TickReceiver};
+pub use watchdog::{Watchdog, WatchdogEvent};
 
 #[cfg(test)]
 mod tests {
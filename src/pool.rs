@@ -0,0 +1,165 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Once,
+    },
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+/// A job arranged by `Scheduler::arrange_fn`.
+pub(crate) type Job = Box<dyn FnOnce() + Send>;
+
+/// Snapshot of the worker pool state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Number of workers, which is also the maximum number of jobs running at the same time.
+    pub workers: usize,
+    /// Jobs expired but waiting for a free worker.
+    pub queue_depth: usize,
+    /// Jobs running right now.
+    pub active: usize,
+    /// Jobs finished, including the panicked ones.
+    pub completed: usize,
+    /// Jobs panicked.
+    pub panicked: usize,
+}
+
+#[derive(Default)]
+struct Stats {
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+}
+
+/// Worker pool running the expired jobs, workers are spawned on the first job.
+pub(crate) struct WorkerPool {
+    workers: usize,
+    sender: Sender<Job>,
+    receiver: Receiver<Job>,
+    spawned: Once,
+    stats: Arc<Stats>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(workers: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        WorkerPool {
+            workers: workers.max(1),
+            sender,
+            receiver,
+            spawned: Once::new(),
+            stats: Arc::default(),
+        }
+    }
+
+    pub(crate) fn execute(&self, job: Job) {
+        self.spawned.call_once(|| {
+            for index in 0..self.workers {
+                let jobs = self.receiver.clone();
+                let stats = self.stats.clone();
+                thread::Builder::new()
+                    .name(format!("xpd-timer-worker-{}", index))
+                    .spawn(move || work(jobs, stats))
+                    .expect("failed to spawn timer worker");
+            }
+        });
+
+        self.sender
+            .send(job)
+            .expect("all workers exited, stop running timer wheel");
+    }
+
+    pub(crate) fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            workers: self.workers,
+            queue_depth: self.receiver.len(),
+            active: self.stats.active.load(Ordering::Relaxed),
+            completed: self.stats.completed.load(Ordering::Relaxed),
+            panicked: self.stats.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn work(jobs: Receiver<Job>, stats: Arc<Stats>) {
+    for job in jobs {
+        stats.active.fetch_add(1, Ordering::Relaxed);
+
+        // a panicked job must not take the worker down with it
+        if let Err(cause) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            stats.panicked.fetch_add(1, Ordering::Relaxed);
+            log::error!("timer job panicked: {}", panic_message(&cause));
+        }
+
+        stats.active.fetch_sub(1, Ordering::Relaxed);
+        stats.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn panic_message(cause: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = cause.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = cause.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_completed(pool: &WorkerPool, completed: usize) -> PoolMetrics {
+        loop {
+            let metrics = pool.metrics();
+            if metrics.completed >= completed {
+                return metrics;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_panic_isolation() {
+        let pool = WorkerPool::new(1);
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        pool.execute(Box::new(|| panic!("boom")));
+        pool.execute(Box::new(move || sender.send("survived").unwrap()));
+
+        assert_eq!(receiver.recv().unwrap(), "survived");
+        let metrics = wait_completed(&pool, 2);
+        assert_eq!(metrics.panicked, 1);
+        assert_eq!(metrics.completed, 2);
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let pool = WorkerPool::new(2);
+        let (release, blocked) = crossbeam_channel::unbounded::<()>();
+
+        for _ in 0..5 {
+            let blocked = blocked.clone();
+            pool.execute(Box::new(move || {
+                blocked.recv().unwrap();
+            }));
+        }
+        while pool.metrics().active < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let metrics = pool.metrics();
+        assert_eq!(metrics.active, 2);
+        assert_eq!(metrics.queue_depth, 3);
+
+        for _ in 0..5 {
+            release.send(()).unwrap();
+        }
+        let metrics = wait_completed(&pool, 5);
+        assert_eq!(metrics.queue_depth, 0);
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::time_wheel::{Alarm, Scheduler};
+
+/// Park the calling thread until a timer of `alarm` wakes it from the timer thread after `wait`.
+fn park(alarm: &Arc<dyn Alarm>, wait: Duration) {
+    if wait.is_zero() {
+        return;
+    }
+    let (wake, parked) = crossbeam_channel::bounded(1);
+    alarm.set(
+        SystemTime::now() + wait,
+        Box::new(move || {
+            let _ = wake.send(());
+        }),
+    );
+    let _ = parked.recv();
+}
+
+/// Tokens of a token bucket, refilled on demand.
+struct Bucket {
+    /// Negative while tokens are reserved by the waiting callers.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: u32) -> Self {
+        Bucket {
+            tokens: capacity as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, capacity: u32, refill: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() / refill.as_secs_f64()).min(capacity as f64);
+        self.updated = now;
+    }
+
+    fn try_acquire(&mut self, n: u32, capacity: u32, refill: Duration) -> bool {
+        self.refill(capacity, refill, Instant::now());
+        if self.tokens < n as f64 {
+            return false;
+        }
+        self.tokens -= n as f64;
+        true
+    }
+
+    /// Reserve `n` tokens, return the wait until they are accumulated,
+    /// `None` without reserving if it is longer than `max_wait`.
+    fn reserve(
+        &mut self,
+        n: u32,
+        capacity: u32,
+        refill: Duration,
+        max_wait: Option<Duration>,
+    ) -> Option<Duration> {
+        if n > capacity {
+            return None;
+        }
+        self.refill(capacity, refill, Instant::now());
+        let missing = (n as f64 - self.tokens).max(0.0);
+        let wait = refill.mul_f64(missing);
+        if max_wait.is_some_and(|max_wait| wait > max_wait) {
+            return None;
+        }
+        self.tokens -= n as f64;
+        Some(wait)
+    }
+
+    /// Time until the bucket is full again.
+    fn until_full(&self, capacity: u32, refill: Duration) -> Duration {
+        refill.mul_f64((capacity as f64 - self.tokens).max(0.0))
+    }
+}
+
+/// A token bucket of `capacity` tokens, refilled by one token every `refill`.
+///
+/// Callers waiting for tokens are parked on the timers of a shared scheduler, so one timer
+/// thread serves many limiters. Tokens are reserved in the order of the calls, a waiting caller
+/// is not overtaken by a later one.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::{time_wheel, RateLimiter};
+/// use std::time::Duration;
+///
+/// let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+/// let limiter = RateLimiter::new(&scheduler, 2, Duration::from_millis(10));
+///
+/// assert!(limiter.try_acquire(2));
+/// assert!(!limiter.try_acquire(1));
+/// // parked for about 10ms
+/// limiter.acquire(1);
+/// ```
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    capacity: u32,
+    refill: Duration,
+    alarm: Arc<dyn Alarm>,
+}
+
+impl RateLimiter {
+    /// New limiter with a full bucket, waiting callers are parked on the timers of `scheduler`.
+    pub fn new<T, K>(scheduler: &Scheduler<T, K>, capacity: u32, refill: Duration) -> Self
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        RateLimiter {
+            bucket: Mutex::new(Bucket::full(capacity)),
+            capacity,
+            refill,
+            alarm: scheduler.alarm(),
+        }
+    }
+
+    /// Take `n` tokens if they are available now.
+    pub fn try_acquire(&self, n: u32) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.try_acquire(n, self.capacity, self.refill)
+    }
+
+    /// Take `n` tokens, waiting for them if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the capacity, they can never be available.
+    pub fn acquire(&self, n: u32) {
+        let wait = self.reserve(n, None);
+        let wait = wait.expect("more tokens than the capacity are acquired");
+        park(&self.alarm, wait);
+    }
+
+    /// Take `n` tokens, waiting at most `timeout` for them.
+    ///
+    /// Return `false` right away if they can not be available in time.
+    pub fn acquire_timeout(&self, n: u32, timeout: Duration) -> bool {
+        match self.reserve(n, Some(timeout)) {
+            Some(wait) => {
+                park(&self.alarm, wait);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn reserve(&self, n: u32, max_wait: Option<Duration>) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.reserve(n, self.capacity, self.refill, max_wait)
+    }
+
+    /// Tokens available now, rounded down, 0 while tokens are reserved.
+    pub fn available(&self) -> u32 {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(self.capacity, self.refill, Instant::now());
+        bucket.tokens.max(0.0) as u32
+    }
+}
+
+struct Client {
+    bucket: Bucket,
+    last_used: Instant,
+}
+
+type Clients<K> = Mutex<HashMap<K, Client>>;
+
+/// A `RateLimiter` per key, e.g. per client.
+///
+/// A key idle for `idle` and with a full bucket again is removed by a timer of the same
+/// scheduler, and starts with a full bucket when it comes back.
+pub struct KeyedRateLimiter<K> {
+    clients: Arc<Clients<K>>,
+    capacity: u32,
+    refill: Duration,
+    idle: Duration,
+    alarm: Arc<dyn Alarm>,
+}
+
+impl<K> KeyedRateLimiter<K>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    /// New limiter of `capacity` and `refill` per key, idle keys are removed after `idle`.
+    pub fn new<T, Q>(
+        scheduler: &Scheduler<T, Q>,
+        capacity: u32,
+        refill: Duration,
+        idle: Duration,
+    ) -> Self
+    where
+        T: Send + 'static,
+        Q: Send + 'static,
+    {
+        KeyedRateLimiter {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            capacity,
+            refill,
+            idle,
+            alarm: scheduler.alarm(),
+        }
+    }
+
+    /// Take `n` tokens of `key` if they are available now.
+    pub fn try_acquire(&self, key: &K, n: u32) -> bool {
+        self.with_bucket(key, |bucket| {
+            bucket.try_acquire(n, self.capacity, self.refill)
+        })
+    }
+
+    /// Take `n` tokens of `key`, waiting for them if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the capacity, they can never be available.
+    pub fn acquire(&self, key: &K, n: u32) {
+        let wait = self.with_bucket(key, |bucket| {
+            bucket.reserve(n, self.capacity, self.refill, None)
+        });
+        let wait = wait.expect("more tokens than the capacity are acquired");
+        park(&self.alarm, wait);
+    }
+
+    /// Take `n` tokens of `key`, waiting at most `timeout` for them.
+    ///
+    /// Return `false` right away if they can not be available in time.
+    pub fn acquire_timeout(&self, key: &K, n: u32, timeout: Duration) -> bool {
+        let wait = self.with_bucket(key, |bucket| {
+            bucket.reserve(n, self.capacity, self.refill, Some(timeout))
+        });
+        match wait {
+            Some(wait) => {
+                park(&self.alarm, wait);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of the keys kept.
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn with_bucket<R>(&self, key: &K, f: impl FnOnce(&mut Bucket) -> R) -> R {
+        let mut clients = self.clients.lock().unwrap();
+        let now = Instant::now();
+        if !clients.contains_key(key) {
+            self.arm_idle(key.clone());
+        }
+        let client = clients.entry(key.clone()).or_insert_with(|| Client {
+            bucket: Bucket::full(self.capacity),
+            last_used: now,
+        });
+        client.last_used = now;
+        f(&mut client.bucket)
+    }
+
+    /// Arm the timer removing a new `key` once it is idle.
+    fn arm_idle(&self, key: K) {
+        let clients = Arc::downgrade(&self.clients);
+        let (alarm, capacity, refill, idle) =
+            (self.alarm.clone(), self.capacity, self.refill, self.idle);
+        self.alarm.set(
+            SystemTime::now() + idle,
+            Box::new(move || expire_idle(clients, key, alarm, capacity, refill, idle)),
+        );
+    }
+}
+
+/// Remove `key` if it is idle with a full bucket, or check it again when it may be.
+fn expire_idle<K: Hash + Eq + Clone + Send + 'static>(
+    clients: Weak<Clients<K>>,
+    key: K,
+    alarm: Arc<dyn Alarm>,
+    capacity: u32,
+    refill: Duration,
+    idle: Duration,
+) {
+    let strong = match clients.upgrade() {
+        Some(clients) => clients,
+        None => return,
+    };
+    let mut map = strong.lock().unwrap();
+    let now = Instant::now();
+    let client = match map.get_mut(&key) {
+        Some(client) => client,
+        None => return,
+    };
+    client.bucket.refill(capacity, refill, now);
+    let later = (client.last_used + idle)
+        .saturating_duration_since(now)
+        .max(client.bucket.until_full(capacity, refill));
+    if later.is_zero() {
+        map.remove(&key);
+        return;
+    }
+
+    let when = SystemTime::now() + later;
+    let rearm = alarm.clone();
+    alarm.set(
+        when,
+        Box::new(move || expire_idle(clients, key, rearm, capacity, refill, idle)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{time_wheel, Builder};
+    use std::thread;
+
+    fn scheduler() -> Scheduler<()> {
+        time_wheel(Duration::from_millis(1)).0
+    }
+
+    #[test]
+    fn test_acquire() {
+        let limiter = RateLimiter::new(&scheduler(), 3, Duration::from_millis(20));
+        assert!(limiter.try_acquire(3));
+        assert!(!limiter.try_acquire(1));
+        assert!(!limiter.acquire_timeout(4, Duration::from_secs(60)));
+        assert!(!limiter.acquire_timeout(2, Duration::from_millis(10)));
+
+        let start = Instant::now();
+        assert!(limiter.acquire_timeout(1, Duration::from_millis(30)));
+        limiter.acquire(1);
+        // 2 tokens refilled, less a bit of the time passed before the first call
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(limiter.available(), 0);
+
+        thread::sleep(Duration::from_millis(70));
+        assert_eq!(limiter.available(), 3);
+    }
+
+    #[test]
+    fn test_waiters_share_scheduler() {
+        let scheduler = scheduler();
+        let limiter = Arc::new(RateLimiter::new(&scheduler, 1, Duration::from_millis(10)));
+        let other = RateLimiter::new(&scheduler, 1, Duration::from_millis(10));
+
+        let start = Instant::now();
+        let waiters = (0..4)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || limiter.acquire(1))
+            })
+            .collect::<Vec<_>>();
+        assert!(other.try_acquire(1));
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        // the first one takes the full bucket, the others wait in turn
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_full_scheduler() {
+        let (scheduler, _receiver) = Builder::new(Duration::from_millis(1))
+            .max_pending(1)
+            .build::<()>();
+        scheduler.arrange(()).after(Duration::from_secs(60));
+
+        // the timers of the limiter are not counted by the limits
+        let limiter = RateLimiter::new(&scheduler, 1, Duration::from_millis(10));
+        limiter.acquire(1);
+        limiter.acquire(1);
+        assert_eq!(scheduler.admission_metrics().pending, 1);
+    }
+
+    #[test]
+    fn test_keyed() {
+        let limiter = KeyedRateLimiter::new(
+            &scheduler(),
+            1,
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+        );
+        assert!(limiter.try_acquire(&"a", 1));
+        assert!(!limiter.try_acquire(&"a", 1));
+        assert!(limiter.try_acquire(&"b", 1));
+        assert!(limiter.acquire_timeout(&"b", 1, Duration::from_millis(20)));
+        assert_eq!(limiter.len(), 2);
+
+        thread::sleep(Duration::from_millis(80));
+        assert!(limiter.is_empty());
+    }
+}
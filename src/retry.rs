@@ -0,0 +1,242 @@
+use std::{
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Sender;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{Backoff, Builder, Scheduler, TickReceiver, TimerId};
+
+/// The id the exhausted items are received with, they are never arranged.
+const EXHAUSTED: TimerId = TimerId::from_u64(u64::MAX);
+
+/// An item due for a retry, or given up on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retry<T> {
+    pub item: T,
+    /// Failed attempts so far, from 1.
+    pub attempt: u32,
+    /// When the first attempt failed.
+    pub first_failed: SystemTime,
+    /// The delay before this retry.
+    pub delay: Duration,
+}
+
+impl<T> Retry<T> {
+    /// Time since the first attempt failed.
+    pub fn elapsed(&self) -> Duration {
+        self.first_failed.elapsed().unwrap_or_default()
+    }
+}
+
+/// Schedules the retries of failed operations by a `Backoff` policy.
+///
+/// Items due for a retry are received by the first receiver returned by `new`, the ones
+/// past the max attempts or the elapsed budget by the second one, right away without a timer.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::{Backoff, RetryScheduler};
+/// use std::time::Duration;
+///
+/// let backoff = Backoff::exponential(Duration::from_millis(10), Duration::from_secs(1));
+/// let (retries, due, exhausted) = RetryScheduler::new(Duration::from_millis(1), backoff);
+/// let retries = retries.max_attempts(2);
+///
+/// retries.schedule_retry("request", 1);
+/// let retry = due.recv().unwrap();
+/// assert_eq!((retry.item, retry.attempt), ("request", 1));
+///
+/// // failed again
+/// assert!(retries.retry_again(retry).is_none());
+/// assert_eq!(exhausted.recv().unwrap().attempt, 2);
+/// ```
+pub struct RetryScheduler<T> {
+    scheduler: Scheduler<Retry<T>>,
+    exhausted: Sender<(TimerId, Retry<T>)>,
+    backoff: Backoff,
+    max_attempts: Option<u32>,
+    max_elapsed: Option<Duration>,
+    rng: Mutex<StdRng>,
+}
+
+impl<T: Debug + Send + 'static> RetryScheduler<T> {
+    /// New retry scheduler with a time wheel of the tick `interval`,
+    /// return it with the receivers of the due and the exhausted items.
+    pub fn new(
+        interval: Duration,
+        backoff: Backoff,
+    ) -> (Self, TickReceiver<Retry<T>>, TickReceiver<Retry<T>>) {
+        let (scheduler, due) = Builder::new(interval).workers(1).build();
+        let (exhausted, receiver) = crossbeam_channel::unbounded();
+        let retries = RetryScheduler {
+            scheduler,
+            exhausted,
+            backoff,
+            max_attempts: None,
+            max_elapsed: None,
+            rng: Mutex::new(StdRng::from_entropy()),
+        };
+        (retries, due, TickReceiver::new(receiver, None))
+    }
+
+    /// Give up after `max_attempts` failed attempts, default is no limit.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Give up when a retry would be later than `max_elapsed` after the first failure, default is no limit.
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// Seed the random generator of `Backoff::DecorrelatedJitter`, default is seeded from the OS.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Schedule the retry of `item` after its failed `attempt`, from 1, the first attempt failing now.
+    ///
+    /// Return `None` if it is exhausted and sent to the exhausted items instead.
+    pub fn schedule_retry(&self, item: T, attempt: u32) -> Option<TimerId> {
+        self.schedule_retry_since(item, attempt, SystemTime::now())
+    }
+
+    /// Schedule the retry of `item` after its failed `attempt`, the first attempt failed at `first_failed`.
+    ///
+    /// For an item failed before, e.g. loaded from a storage, so its elapsed budget is kept.
+    pub fn schedule_retry_since(
+        &self,
+        item: T,
+        attempt: u32,
+        first_failed: SystemTime,
+    ) -> Option<TimerId> {
+        let retry = Retry {
+            item,
+            attempt: attempt.max(1),
+            first_failed,
+            delay: Duration::ZERO,
+        };
+        self.schedule(retry, None)
+    }
+
+    /// Schedule the next retry of a `retry` that failed again,
+    /// keeping its elapsed budget and its previous delay.
+    pub fn retry_again(&self, mut retry: Retry<T>) -> Option<TimerId> {
+        retry.attempt += 1;
+        let previous = retry.delay;
+        self.schedule(retry, Some(previous))
+    }
+
+    fn schedule(&self, mut retry: Retry<T>, previous: Option<Duration>) -> Option<TimerId> {
+        if self
+            .max_attempts
+            .is_some_and(|max_attempts| retry.attempt >= max_attempts)
+        {
+            self.give_up(retry);
+            return None;
+        }
+
+        let delay = {
+            let mut rng = self.rng.lock().unwrap();
+            self.backoff.delay(retry.attempt, previous, &mut *rng)
+        };
+        let when = SystemTime::now() + delay;
+        let over_budget = self.max_elapsed.is_some_and(|max_elapsed| {
+            when.duration_since(retry.first_failed).unwrap_or_default() > max_elapsed
+        });
+        if over_budget {
+            self.give_up(retry);
+            return None;
+        }
+
+        retry.delay = delay;
+        Some(self.scheduler.arrange(retry).at(when))
+    }
+
+    fn give_up(&self, retry: Retry<T>) {
+        // nobody may wait for the exhausted items
+        let _ = self.exhausted.send((EXHAUSTED, retry));
+    }
+
+    /// Cancel a scheduled retry, return `false` if it is not pending.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    /// Number of the scheduled retries.
+    pub fn pending_count(&self) -> usize {
+        self.scheduler.pending_count()
+    }
+}
+
+impl<T> Drop for RetryScheduler<T> {
+    fn drop(&mut self) {
+        // the pending retries are dropped, not delivered without their scheduler
+        self.scheduler.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_attempts() {
+        let backoff = Backoff::Constant(Duration::from_millis(5));
+        let (retries, due, exhausted) = RetryScheduler::new(Duration::from_millis(1), backoff);
+        let retries = retries.max_attempts(3);
+
+        assert!(retries.schedule_retry("job", 1).is_some());
+        let retry = due.recv().unwrap();
+        assert_eq!(retry.attempt, 1);
+        assert_eq!(retry.delay, Duration::from_millis(5));
+        assert!(retry.elapsed() >= Duration::from_millis(5));
+
+        assert!(retries.retry_again(retry).is_some());
+        let retry = due.recv().unwrap();
+        assert_eq!(retry.attempt, 2);
+        assert!(retries.retry_again(retry).is_none());
+        let retry = exhausted.recv().unwrap();
+        assert_eq!((retry.item, retry.attempt), ("job", 3));
+
+        // already out of attempts
+        assert!(retries.schedule_retry("late", 3).is_none());
+        assert_eq!(retries.pending_count(), 0);
+        assert_eq!(exhausted.recv().unwrap().item, "late");
+    }
+
+    #[test]
+    fn test_budget_and_cancel() {
+        let backoff = Backoff::exponential(Duration::from_millis(20), Duration::from_secs(1));
+        let (retries, due, exhausted) = RetryScheduler::new(Duration::from_millis(1), backoff);
+        let retries = retries.max_elapsed(Duration::from_millis(50)).seed(1);
+
+        // 20ms, then 20 + 40ms is past the budget
+        retries.schedule_retry(1, 1);
+        let retry = due.recv().unwrap();
+        assert!(retries.retry_again(retry).is_none());
+        let retry = exhausted.recv().unwrap();
+        assert_eq!((retry.item, retry.attempt), (1, 2));
+
+        let id = retries.schedule_retry(2, 1).unwrap();
+        assert_eq!(retries.pending_count(), 1);
+        assert!(retries.cancel(id));
+        assert_eq!(retries.pending_count(), 0);
+
+        // failed long enough ago to be past the budget
+        let first_failed = SystemTime::now() - Duration::from_millis(40);
+        assert!(retries.schedule_retry_since(3, 2, first_failed).is_none());
+        assert_eq!(exhausted.recv().unwrap().first_failed, first_failed);
+
+        retries.schedule_retry(4, 1);
+        drop(retries);
+        assert!(due.recv().is_err());
+    }
+}
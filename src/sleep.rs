@@ -0,0 +1,255 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::SystemTime,
+};
+
+use crate::time_wheel::Alarm;
+use crate::{TimerError, TimerId, TimerResult};
+
+/// The waker registered in the wheel, it forwards to the waker of the latest poll.
+struct Registration {
+    fired: AtomicBool,
+    waker: Mutex<Waker>,
+}
+
+impl Wake for Registration {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.fired.store(true, Ordering::Release);
+        self.waker.lock().unwrap().wake_by_ref();
+    }
+}
+
+/// A future completing at a deadline, created by `Scheduler::sleep` or `Scheduler::sleep_until`.
+///
+/// It registers a waker in the wheel when it is first polled, and the timer thread wakes it,
+/// so it works under any executor. Dropping it removes the registration.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::time_wheel;
+/// use std::time::{Duration, SystemTime};
+///
+/// # fn block_on<F: std::future::Future>(future: F) -> F::Output {
+/// #     use std::{pin::pin, sync::Arc, task::{Context, Poll, Wake}, thread::{self, Thread}};
+/// #     struct Unpark(Thread);
+/// #     impl Wake for Unpark {
+/// #         fn wake(self: Arc<Self>) { self.0.unpark() }
+/// #     }
+/// #     let waker = Arc::new(Unpark(thread::current())).into();
+/// #     let mut future = pin!(future);
+/// #     loop {
+/// #         match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+/// #             Poll::Ready(output) => return output,
+/// #             Poll::Pending => thread::park(),
+/// #         }
+/// #     }
+/// # }
+/// let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+/// let sleep = scheduler.sleep(Duration::from_millis(10));
+/// let deadline = sleep.deadline();
+///
+/// block_on(sleep);
+/// assert!(SystemTime::now() >= deadline);
+/// ```
+pub struct Sleep {
+    alarm: Arc<dyn Alarm>,
+    deadline: SystemTime,
+    /// The timer in the wheel, `None` until it is first polled and once it is completed.
+    registered: Option<(TimerId, Arc<Registration>)>,
+}
+
+impl Sleep {
+    pub(crate) fn new(alarm: Arc<dyn Alarm>, deadline: SystemTime) -> Self {
+        Sleep {
+            alarm,
+            deadline,
+            registered: None,
+        }
+    }
+
+    /// When it completes.
+    pub fn deadline(&self) -> SystemTime {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        let fired = self
+            .registered
+            .as_ref()
+            .is_some_and(|(_, registration)| registration.fired.load(Ordering::Acquire));
+        fired || SystemTime::now() >= self.deadline
+    }
+
+    /// Remove the registration from the wheel, unless it has fired.
+    fn unregister(&mut self) {
+        if let Some((id, registration)) = self.registered.take() {
+            if !registration.fired.load(Ordering::Acquire) {
+                self.alarm.discard(id);
+            }
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            self.unregister();
+            return Poll::Ready(());
+        }
+
+        match &self.registered {
+            Some((_, registration)) => {
+                let mut waker = registration.waker.lock().unwrap();
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let registration = Arc::new(Registration {
+                    fired: AtomicBool::new(false),
+                    waker: Mutex::new(cx.waker().clone()),
+                });
+                let id = self
+                    .alarm
+                    .wake_at(self.deadline, Waker::from(registration.clone()));
+                self.registered = Some((id, registration));
+            }
+        }
+
+        // it may fire before the waker is in place
+        if self.is_elapsed() {
+            self.unregister();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// A future running `F` until a deadline, created by `Scheduler::timeout`.
+///
+/// It completes with the output of `F`, or with `TimerError::Elapsed` if the deadline comes first.
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+impl<F: Future> Timeout<F> {
+    pub(crate) fn new(future: F, sleep: Sleep) -> Self {
+        Timeout {
+            future: Box::pin(future),
+            sleep,
+        }
+    }
+
+    /// When it fails with `TimerError::Elapsed`.
+    pub fn deadline(&self) -> SystemTime {
+        self.sleep.deadline()
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = TimerResult<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(TimerError::Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_wheel;
+    use std::{
+        future::poll_fn,
+        pin::pin,
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sleep() {
+        let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+
+        let sleep = scheduler.sleep(Duration::from_millis(20));
+        let deadline = sleep.deadline();
+        assert!(!sleep.is_elapsed());
+        block_on(sleep);
+        assert!(SystemTime::now() >= deadline);
+
+        // dropped before it completes
+        let mut sleep = scheduler.sleep(Duration::from_secs(60));
+        let waker = Arc::new(Unpark(thread::current())).into();
+        assert!(Pin::new(&mut sleep)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(scheduler.timer_count(), 1);
+        drop(sleep);
+        assert_eq!(scheduler.timer_count(), 0);
+
+        block_on(scheduler.sleep_until(SystemTime::now()));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (scheduler, _) = time_wheel::<()>(Duration::from_millis(1));
+
+        let quick = scheduler.timeout(Duration::from_secs(60), async { 7 });
+        assert_eq!(block_on(quick).unwrap(), 7);
+        assert_eq!(scheduler.timer_count(), 0);
+
+        let never = poll_fn(|_| Poll::<()>::Pending);
+        let slow = scheduler.timeout(Duration::from_millis(10), never);
+        assert!(matches!(block_on(slow), Err(TimerError::Elapsed)));
+
+        let nested = async {
+            scheduler.sleep(Duration::from_millis(5)).await;
+            "done"
+        };
+        let result = block_on(scheduler.timeout(Duration::from_millis(500), nested));
+        assert_eq!(result.unwrap(), "done");
+    }
+}
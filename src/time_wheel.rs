@@ -13,126 +13,1506 @@
 ///
 /// In this example, a task named "task1" is scheduled to run 5 seconds later.
 use std::{
-    fmt::Debug,
-    mem,
-    sync::{Arc, Mutex},
-    thread::{self, JoinHandle},
-    time::{Duration, Instant, SystemTime},
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime},
 };
 
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, Sender};
+use rand::{rngs::StdRng, SeedableRng};
 
-use crate::core::Wheel;
-use crate::{TimerError, TimerResult};
+use crate::admission::{Admission, AdmissionMetrics};
+use crate::pool::{Job, PoolMetrics, WorkerPool};
+use crate::{
+    Builder, Deadline, DeadlineToken, Interval, Jitter, Occurrences, RRule, TimerError, TimerId,
+    TimerResult,
+};
+#[cfg(feature = "async")]
+use crate::{Sleep, Timeout};
+#[cfg(feature = "async")]
+use std::{future::Future, task::Waker};
+
+mod outbox;
+mod shard;
+
+use outbox::Router;
+use shard::Shard;
+
+/// An expired entity with the id of its task.
+type Delivery<T> = (TimerId, T);
+
+/// Subscribers of the named topics, shared with the timer thread.
+type Topics<T> = Arc<RwLock<HashMap<String, Sender<Delivery<T>>>>>;
+
+/// Acknowledges a delivered task of any shard, see `TickReceiver::ack`.
+type Acker = Arc<dyn Fn(TimerId) -> bool + Send + Sync>;
+
+/// Sets and cancels jobs of a scheduler without borrowing it, for the helpers outliving a borrow.
+pub(crate) trait Alarm: Send + Sync {
+    /// Run `job` at `when`, right in the timer thread.
+    ///
+    /// The job must be quick and must not wait for the scheduler, e.g. by `cancel`.
+    /// Timers of an alarm are not counted by the limits of the pending tasks.
+    fn set(&self, when: SystemTime, job: Job) -> TimerId;
+
+    /// Cancel a job set by this alarm, return `false` if it is not pending.
+    fn cancel(&self, id: TimerId) -> bool;
+
+    /// Time from the start of the wheel to the tick it is at, counted on the monotonic clock,
+    /// so a step of the system time does not move it.
+    fn elapsed(&self) -> Duration;
+
+    /// The system time `elapsed` after the start of the wheel, a deadline on a tick expires in that tick.
+    fn time_of(&self, elapsed: Duration) -> SystemTime;
+
+    /// The tick interval of the wheel.
+    fn interval(&self) -> Duration;
+
+    /// Wake a task at `when`, like `set`.
+    #[cfg(feature = "async")]
+    fn wake_at(&self, when: SystemTime, waker: Waker) -> TimerId;
+
+    /// Cancel a timer of this alarm without waiting for the timer thread, for `Drop` or a job of `set`.
+    fn discard(&self, id: TimerId);
+}
+
+/// The alarm of one shard.
+struct ShardAlarm<T, K> {
+    shard: Shard<T, K>,
+}
+
+impl<T: Send + 'static, K: Send + 'static> Alarm for ShardAlarm<T, K> {
+    fn set(&self, when: SystemTime, job: Job) -> TimerId {
+        self.arrange(Payload::Alarm(job), when)
+    }
+
+    fn cancel(&self, id: TimerId) -> bool {
+        self.shard.cancel(id)
+    }
+
+    fn elapsed(&self) -> Duration {
+        let epoch = self.shard.epoch();
+        let nanos = epoch.interval().as_nanos() * epoch.ticks() as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    fn time_of(&self, elapsed: Duration) -> SystemTime {
+        self.shard.epoch().time_of(elapsed)
+    }
+
+    fn interval(&self) -> Duration {
+        self.shard.epoch().interval()
+    }
+
+    #[cfg(feature = "async")]
+    fn wake_at(&self, when: SystemTime, waker: Waker) -> TimerId {
+        self.arrange(Payload::Wake(waker), when)
+    }
+
+    fn discard(&self, id: TimerId) {
+        self.shard.discard(id);
+    }
+}
+
+impl<T, K> ShardAlarm<T, K> {
+    /// Arrange an internal timer, bypassing the admission.
+    fn arrange(&self, payload: Payload<T>, when: SystemTime) -> TimerId {
+        let envelope = Envelope {
+            payload,
+            topic: None,
+            priority: 0,
+            key: None,
+            tags: Vec::new(),
+            attempts: 0,
+            recurrence: None,
+        };
+        self.shard.arrange(envelope, when)
+    }
+}
+
+/// What happens when a task expires.
+enum Payload<T> {
+    /// Deliver the entity to a receiver.
+    Entity(T),
+    /// Run the job on the worker pool.
+    Job(Job),
+    /// Run the job of an `Alarm`, right in the timer thread.
+    Alarm(Job),
+    /// Wake a task, right in the timer thread.
+    #[cfg(feature = "async")]
+    Wake(Waker),
+}
+
+impl<T> Payload<T> {
+    /// Whether it is a task arranged by the user, counted by the admission and the queries,
+    /// the internal timers of an `Alarm` are not.
+    fn is_task(&self) -> bool {
+        match self {
+            Payload::Entity(_) | Payload::Job(_) => true,
+            Payload::Alarm(_) => false,
+            #[cfg(feature = "async")]
+            Payload::Wake(_) => false,
+        }
+    }
+}
+
+/// A payload together with how it should be delivered.
+struct Envelope<T, K> {
+    payload: Payload<T>,
+    topic: Option<String>,
+    priority: u8,
+    /// At most one task of a key is pending.
+    key: Option<K>,
+    tags: Vec<String>,
+    /// Times the entity has been delivered without an ack, only counted in reliable mode.
+    attempts: u32,
+    /// The next occurrences of a recurring task, see `InnerScheduler::rrule`.
+    recurrence: Option<Box<Recurrence<T>>>,
+}
+
+/// The occurrences after the pending one of a recurring task.
+struct Recurrence<T> {
+    occurrences: Occurrences,
+    /// Copies the entity delivered at each occurrence but the last one.
+    cloner: fn(&T) -> T,
+}
+
+impl<T: Debug, K> Debug for Envelope<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.payload {
+            Payload::Entity(entity) => f
+                .debug_struct("Envelope")
+                .field("entity", entity)
+                .field("topic", &self.topic)
+                .field("priority", &self.priority)
+                .field("tags", &self.tags)
+                .field("attempts", &self.attempts)
+                .finish(),
+            Payload::Job(_) | Payload::Alarm(_) => {
+                f.debug_struct("Envelope").field("job", &"..").finish()
+            }
+            #[cfg(feature = "async")]
+            Payload::Wake(_) => f.debug_struct("Envelope").field("waker", &"..").finish(),
+        }
+    }
+}
 
 /// Scheduler struct, which schedules tasks to run at a specific time.
-pub struct Scheduler<T> {
-    handler: JoinHandle<()>,
-    entities: Arc<Mutex<Vec<(T, SystemTime)>>>,
+///
+/// Tasks of a shard are delivered in the order of their deadlines,
+/// and tasks with the same deadline in the order they are arranged.
+///
+/// `K` is the type of the keys of `arrange_keyed`, build a keyed scheduler by `Builder::build_keyed`.
+pub struct Scheduler<T, K = ()> {
+    shards: Vec<Shard<T, K>>,
+    /// Round robin cursor over the shards.
+    next_shard: AtomicUsize,
+    topics: Topics<T>,
+    /// Capacity of the receivers, `None` for unbounded.
+    capacity: Option<usize>,
+    pool: Arc<WorkerPool>,
+    /// Limits of the pending tasks, `None` if there is no limit.
+    admission: Option<Arc<Admission>>,
+    /// Acks of the reliable mode, `None` if deliveries are not acked.
+    acker: Option<Acker>,
+    /// Jitter of `InnerScheduler::after`.
+    jitter: Option<Jitter>,
+    rng: Mutex<StdRng>,
+}
+
+/// Redelivery of the entities not acked in time, see `Builder::build_reliable`.
+pub(crate) struct Reliable<T> {
+    pub(crate) visibility_timeout: Duration,
+    pub(crate) max_redeliveries: u32,
+    /// Copies the entity kept for redelivery.
+    pub(crate) cloner: fn(&T) -> T,
+    /// Entities redelivered `max_redeliveries` times without an ack.
+    pub(crate) dead_letters: Sender<Delivery<T>>,
+}
+
+impl<T> Clone for Reliable<T> {
+    fn clone(&self) -> Self {
+        Reliable {
+            visibility_timeout: self.visibility_timeout,
+            max_redeliveries: self.max_redeliveries,
+            cloner: self.cloner,
+            dead_letters: self.dead_letters.clone(),
+        }
+    }
 }
 
 /// InnerScheduler struct, which is used to schedule tasks internally.
-pub struct InnerScheduler<'a, T>(&'a Scheduler<T>, T);
+pub struct InnerScheduler<'a, T, K = ()> {
+    scheduler: &'a Scheduler<T, K>,
+    payload: Payload<T>,
+    topic: Option<String>,
+    shard: Option<usize>,
+    priority: u8,
+    key: Option<K>,
+    tags: Vec<String>,
+    recurrence: Option<Box<Recurrence<T>>>,
+}
+
+impl<'a, T, K> InnerScheduler<'a, T, K> {
+    /// Deliver the task to the subscriber of `topic` instead of the default receiver.
+    ///
+    /// If nobody subscribed to `topic` when the task expires, it goes to the default receiver.
+    /// Jobs of `Scheduler::arrange_fn` always run on the worker pool, the topic is ignored.
+    pub fn on(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Priority of the task, default is 0.
+    ///
+    /// Tasks expiring in the same tick are delivered by priority, higher first,
+    /// and a higher priority task goes first while the receiver is full.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Attach a tag to the task, call it again for more tags.
+    ///
+    /// Tasks of a tag are cancelled or shifted together, see `Scheduler::cancel_tag`.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Assign the task to a shard by the hash of `key` instead of round robin.
+    ///
+    /// Tasks with the same key always go to the same shard.
+    /// A keyed task always goes to the shard of its key, this is ignored.
+    pub fn shard_by(mut self, key: impl Hash) -> Self {
+        if self.key.is_none() {
+            self.shard = Some(self.scheduler.shard_of(key));
+        }
+        self
+    }
 
-impl<'a, T> InnerScheduler<'a, T> {
     /// Schedule a task to run at a specific time.
-    pub fn at(self, when: SystemTime) {
-        let InnerScheduler(scheduler, entity) = self;
+    ///
+    /// # Panics
+    ///
+    /// Panics if a limit of `Builder::max_pending` or `Builder::tag_limit` is exceeded, use `try_at` with limits.
+    pub fn at(self, when: SystemTime) -> TimerId {
+        self.try_at(when).expect("failed to arrange the task")
+    }
+
+    /// Schedule a task to run after a specific duration, plus the jitter of `Builder::jitter` if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a limit is exceeded like `at`.
+    pub fn after(self, after: Duration) -> TimerId {
+        self.try_after(after).expect("failed to arrange the task")
+    }
+
+    /// Schedule a task to run after `base` plus a random delay drawn from `jitter`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a limit is exceeded like `at`.
+    pub fn after_jittered(self, base: Duration, jitter: Jitter) -> TimerId {
+        self.try_after_jittered(base, jitter)
+            .expect("failed to arrange the task")
+    }
+
+    /// Schedule a task to run at a specific time, fail with `TimerError::CapacityExceeded` past a limit.
+    ///
+    /// A keyed task replacing the pending one of its key takes over its room, it is not rejected when full.
+    pub fn try_at(self, when: SystemTime) -> TimerResult<TimerId> {
+        let InnerScheduler {
+            scheduler,
+            payload,
+            topic,
+            shard,
+            priority,
+            key,
+            tags,
+            recurrence,
+        } = self;
+
+        let shard = shard.unwrap_or_else(|| {
+            scheduler.next_shard.fetch_add(1, Ordering::Relaxed) % scheduler.shards.len()
+        });
+        let shard = &scheduler.shards[shard];
 
-        let mut entries = scheduler.entities.lock().unwrap();
+        let mut envelope = Envelope {
+            payload,
+            topic,
+            priority,
+            key,
+            tags,
+            attempts: 0,
+            recurrence,
+        };
+        if let Some(admission) = &scheduler.admission {
+            let keyed = envelope.key.is_some();
+            if keyed && admission.try_acquire(&envelope.tags, 1).is_ok() {
+                return Ok(shard.arrange(envelope, when));
+            }
+            if keyed {
+                // full, but a task replacing the pending one of its key takes over its room
+                envelope = match shard.replace(envelope, when) {
+                    Ok(id) => return Ok(id),
+                    Err(envelope) => envelope,
+                };
+            }
+            admission.acquire(&envelope.tags, 1)?;
+        }
+        Ok(shard.arrange(envelope, when))
+    }
 
-        entries.push((entity, when));
-        scheduler.handler.thread().unpark();
+    /// Schedule a task to run after a specific duration, fail like `try_at`.
+    pub fn try_after(self, after: Duration) -> TimerResult<TimerId> {
+        match self.scheduler.jitter {
+            Some(jitter) => self.try_after_jittered(after, jitter),
+            None => self.try_at(SystemTime::now() + after),
+        }
     }
 
-    /// Schedule a task to run after a specific duration.
-    pub fn after(self, after: Duration) {
-        self.at(SystemTime::now() + after);
+    /// Schedule a task to run after `base` plus a random delay drawn from `jitter`, fail like `try_at`.
+    pub fn try_after_jittered(self, base: Duration, jitter: Jitter) -> TimerResult<TimerId> {
+        let delay = jitter.sample(&mut *self.scheduler.rng.lock().unwrap());
+        self.try_at(SystemTime::now() + base + delay)
     }
 }
 
-impl<T> Scheduler<T> {
+impl<'a, T: Clone, K> InnerScheduler<'a, T, K> {
+    /// Deliver a copy of the entity at every occurrence of `rule` from now on, under one id.
+    ///
+    /// The task stays pending until its last occurrence, and cancelling it, its key or a tag
+    /// stops the rest. `Scheduler::deadline` of it is the next occurrence. In reliable mode
+    /// only the last occurrence waits for an ack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rule` has no occurrence from now on, the task is a job of `arrange_fn`
+    /// which can only run once, or a limit is exceeded like `at`.
+    pub fn rrule(self, rule: RRule) -> TimerId {
+        self.try_rrule(rule).expect("failed to arrange the task")
+    }
+
+    /// Deliver the entity at every occurrence of `rule` like `rrule`, fail with
+    /// `TimerError::InvalidRule` if it has no occurrence from now on or the task is a job.
+    pub fn try_rrule(mut self, rule: RRule) -> TimerResult<TimerId> {
+        if !matches!(self.payload, Payload::Entity(_)) {
+            return Err(TimerError::InvalidRule(
+                "a job runs only once, it can not recur".to_string(),
+            ));
+        }
+        let now = SystemTime::now();
+        let mut occurrences = rule.occurrences_from(now);
+        let first = occurrences
+            .find(|when| *when >= now)
+            .ok_or_else(|| TimerError::InvalidRule("no occurrence from now on".to_string()))?;
+        self.recurrence = Some(Box::new(Recurrence {
+            occurrences,
+            cloner: T::clone,
+        }));
+        self.try_at(first)
+    }
+}
+
+impl<T, K> Scheduler<T, K> {
     /// Arrange a task to be scheduled.
-    pub fn arrange(&self, entity: T) -> InnerScheduler<T> {
-        return InnerScheduler(self, entity);
+    pub fn arrange(&self, entity: T) -> InnerScheduler<'_, T, K> {
+        self.inner(Payload::Entity(entity))
+    }
+
+    /// Arrange a job to run on the worker pool when it expires.
+    ///
+    /// A panic in the job is caught and reported, the worker keeps running the other jobs.
+    pub fn arrange_fn(&self, job: impl FnOnce() + Send + 'static) -> InnerScheduler<'_, T, K> {
+        self.inner(Payload::Job(Box::new(job)))
+    }
+
+    fn inner(&self, payload: Payload<T>) -> InnerScheduler<'_, T, K> {
+        InnerScheduler {
+            scheduler: self,
+            payload,
+            topic: None,
+            shard: None,
+            priority: 0,
+            key: None,
+            tags: Vec::new(),
+            recurrence: None,
+        }
+    }
+
+    /// Arrange a batch of tasks, return their ids in the same order.
+    ///
+    /// Each shard receives its part of the batch at once and wakes only once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the batch exceeds `Builder::max_pending`, use `try_arrange_many` with limits.
+    pub fn arrange_many<D: Into<Deadline>>(
+        &self,
+        entities: impl IntoIterator<Item = (T, D)>,
+    ) -> Vec<TimerId> {
+        self.try_arrange_many(entities)
+            .expect("failed to arrange the tasks")
+    }
+
+    /// Arrange a batch of tasks, the whole batch fails with `TimerError::CapacityExceeded` past the limit.
+    pub fn try_arrange_many<D: Into<Deadline>>(
+        &self,
+        entities: impl IntoIterator<Item = (T, D)>,
+    ) -> TimerResult<Vec<TimerId>> {
+        let entities = entities.into_iter().collect::<Vec<_>>();
+        if let Some(admission) = &self.admission {
+            admission.acquire(&[], entities.len())?;
+        }
+        let count = self.shards.len();
+        let first = self.next_shard.fetch_add(entities.len(), Ordering::Relaxed);
+
+        let mut batches = (0..count).map(|_| Vec::new()).collect::<Vec<_>>();
+        for (i, (entity, deadline)) in entities.into_iter().enumerate() {
+            let envelope = Envelope {
+                payload: Payload::Entity(entity),
+                topic: None,
+                priority: 0,
+                key: None,
+                tags: Vec::new(),
+                attempts: 0,
+                recurrence: None,
+            };
+            batches[(first + i) % count].push((envelope, deadline.into().when()));
+        }
+        let total = batches.iter().map(Vec::len).sum();
+
+        let mut ids = batches
+            .into_iter()
+            .zip(&self.shards)
+            .map(|(batch, shard)| shard.arrange_many(batch).into_iter())
+            .collect::<Vec<_>>();
+        Ok((0..total)
+            .map(|i| ids[(first + i) % count].next().unwrap())
+            .collect())
+    }
+
+    /// Cancel a pending task, return `false` if it has expired or been cancelled.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        match self.shards.get(id.shard()) {
+            Some(shard) => shard.cancel(id),
+            None => false,
+        }
+    }
+
+    /// Cancel a pending task without waiting for the timer thread.
+    pub(crate) fn discard(&self, id: TimerId) {
+        if let Some(shard) = self.shards.get(id.shard()) {
+            shard.discard(id);
+        }
+    }
+
+    /// Move a pending task to `when`, it keeps its id, its key and its tags.
+    ///
+    /// Return `false` if it has expired or been cancelled.
+    pub fn reschedule(&self, id: TimerId, when: SystemTime) -> bool {
+        match self.shards.get(id.shard()) {
+            Some(shard) => shard.reschedule(id, when),
+            None => false,
+        }
+    }
+
+    /// Deadline of a pending task, `None` if it has expired or been cancelled.
+    pub fn deadline(&self, id: TimerId) -> Option<SystemTime> {
+        self.shards.get(id.shard())?.deadline(id)
+    }
+
+    /// Time left until a pending task expires, zero if its deadline has passed but it is not delivered yet.
+    pub fn remaining(&self, id: TimerId) -> Option<Duration> {
+        let deadline = self.deadline(id)?;
+        Some(
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+
+    /// Whether a task is still waiting to expire.
+    pub fn is_pending(&self, id: TimerId) -> bool {
+        self.deadline(id).is_some()
+    }
+
+    /// Number of pending tasks of all shards, including the jobs,
+    /// the timers of deadline tokens, intervals and sleeps are not tasks.
+    pub fn pending_count(&self) -> usize
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.shards.iter().map(Shard::pending_count).sum()
+    }
+
+    /// Number of pending timers of all shards, the internal ones included.
+    #[cfg(test)]
+    pub(crate) fn timer_count(&self) -> usize
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.shards.iter().map(Shard::timer_count).sum()
+    }
+
+    /// The earliest deadline of the pending tasks, `None` if there is no pending task.
+    ///
+    /// The timers of deadline tokens, intervals and sleeps are not tasks, like `pending_count`.
+    pub fn next_deadline(&self) -> Option<SystemTime>
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.shards.iter().filter_map(Shard::next_deadline).min()
+    }
+
+    /// Cancel the pending tasks of `tag`, return their entities.
+    ///
+    /// Entities of a shard are returned in the order they are arranged, jobs are dropped.
+    pub fn cancel_tag(&self, tag: &str) -> Vec<T> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.cancel_tag(tag))
+            .collect()
+    }
+
+    /// Stop the timer threads, the pending tasks are dropped and the receivers disconnect.
+    ///
+    /// For the helpers owning a private scheduler, which may still be shared by their threads.
+    pub(crate) fn shutdown(&self) {
+        self.shards.iter().for_each(Shard::shutdown);
+    }
+
+    /// Number of pending tasks of `tag`.
+    pub fn count_tag(&self, tag: &str) -> usize {
+        self.shards.iter().map(|shard| shard.count_tag(tag)).sum()
+    }
+
+    /// Postpone the pending tasks of `tag` by `delay`, return the number of tasks shifted.
+    pub fn shift_tag(&self, tag: &str, delay: Duration) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.shift_tag(tag, delay))
+            .sum()
+    }
+
+    /// Number of shards, each shard runs a timer thread.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_of(&self, key: impl Hash) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    /// Metrics of the worker pool running the jobs of `arrange_fn`.
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        self.pool.metrics()
+    }
+
+    /// Pending tasks and rejections of the limits, all zero if there is no limit.
+    pub fn admission_metrics(&self) -> AdmissionMetrics {
+        self.admission
+            .as_ref()
+            .map_or(AdmissionMetrics::default(), |admission| admission.metrics())
+    }
+
+    pub(crate) fn acker(&self) -> Option<Acker> {
+        self.acker.clone()
+    }
+
+    /// A token tripped by the timer thread after `after`, see `DeadlineToken`.
+    ///
+    /// The timer of a token bypasses the admission, it is neither limited nor counted as a pending task.
+    pub fn deadline_token(&self, after: Duration) -> DeadlineToken
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        DeadlineToken::new(self.alarm(), SystemTime::now() + after)
+    }
+
+    /// Tick every `period` from now, see `Interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn interval(&self, period: Duration) -> Interval
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        Interval::new(self.alarm(), period)
+    }
+
+    /// A future completing at `after` from now, see `Sleep`.
+    #[cfg(feature = "async")]
+    pub fn sleep(&self, after: Duration) -> Sleep
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        self.sleep_until(SystemTime::now() + after)
+    }
+
+    /// A future completing at `deadline`, see `Sleep`.
+    #[cfg(feature = "async")]
+    pub fn sleep_until(&self, deadline: SystemTime) -> Sleep
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        Sleep::new(self.alarm(), deadline)
+    }
+
+    /// Run `future` for at most `after`, it fails with `TimerError::Elapsed` past that.
+    #[cfg(feature = "async")]
+    pub fn timeout<F: Future>(&self, after: Duration, future: F) -> Timeout<F>
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        Timeout::new(future, self.sleep(after))
+    }
+
+    /// An alarm on a shard picked round robin.
+    pub(crate) fn alarm(&self) -> Arc<dyn Alarm>
+    where
+        T: Send + 'static,
+        K: Send + 'static,
+    {
+        let shard = self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        Arc::new(ShardAlarm {
+            shard: self.shards[shard].clone(),
+        })
+    }
+
+    /// Subscribe to a named topic, tasks arranged `on(topic)` will be received by the returned receiver.
+    ///
+    /// Subscribing to the same topic again replaces the previous subscriber.
+    pub fn subscribe(&self, topic: impl Into<String>) -> TickReceiver<T> {
+        let (sender, receiver) = channel(self.capacity);
+        self.topics.write().unwrap().insert(topic.into(), sender);
+        TickReceiver::new(receiver, self.acker.clone())
+    }
+}
+
+impl<T: Clone + Send + 'static, K: 'static> Scheduler<T, K> {
+    /// Pending entities with their ids and deadlines, sorted by deadline, jobs are left out.
+    ///
+    /// Tasks with the same deadline are in the order they are arranged within a shard.
+    pub fn snapshot(&self) -> Vec<(TimerId, SystemTime, T)> {
+        let mut snapshot = self
+            .shards
+            .iter()
+            .flat_map(Shard::snapshot)
+            .collect::<Vec<_>>();
+        snapshot.sort_by_key(|(id, when, _)| (*when, *id));
+        snapshot
+    }
+}
+
+impl<T, K: Hash + Clone> Scheduler<T, K> {
+    /// Arrange a task with a key, at most one task of a key is pending.
+    ///
+    /// Arranging a key again replaces the deadline and the entity of the pending one.
+    pub fn arrange_keyed(&self, key: K, entity: T) -> InnerScheduler<'_, T, K> {
+        let shard = self.shard_of(&key);
+        InnerScheduler {
+            shard: Some(shard),
+            key: Some(key),
+            ..self.inner(Payload::Entity(entity))
+        }
+    }
+
+    /// Cancel the pending task of `key`, return `false` if there is none.
+    pub fn cancel_key(&self, key: &K) -> bool {
+        self.shards[self.shard_of(key)].cancel_key(key.clone())
     }
 }
 
 /// TickReceiver struct, which receives ticks from the time wheel.
-pub struct TickReceiver<T>(Receiver<T>);
+pub struct TickReceiver<T> {
+    receiver: Receiver<Delivery<T>>,
+    acker: Option<Acker>,
+}
 
 impl<T> TickReceiver<T> {
+    pub(crate) fn new(receiver: Receiver<Delivery<T>>, acker: Option<Acker>) -> Self {
+        TickReceiver { receiver, acker }
+    }
+
     /// Receive a tick from the time wheel.
     pub fn recv(&self) -> TimerResult<T> {
-        match self.0.recv() {
+        self.recv_with_id().map(|(_, entity)| entity)
+    }
+
+    /// Receive a tick from the time wheel together with the id of its task, for `ack`.
+    pub fn recv_with_id(&self) -> TimerResult<(TimerId, T)> {
+        match self.receiver.recv() {
             Ok(result) => Ok(result),
             Err(err) => Err(TimerError::RecvError(err.to_string())),
         }
     }
+
+    /// Acknowledge a delivered task of a reliable time wheel, so it is not delivered again.
+    ///
+    /// Return `false` if the task is not waiting for an ack, e.g. it has gone to the dead letters,
+    /// or the time wheel is not built by `Builder::build_reliable`.
+    pub fn ack(&self, id: TimerId) -> bool {
+        match &self.acker {
+            Some(acker) => acker(id),
+            None => false,
+        }
+    }
 }
 
 /// Create a time wheel with a specific tick interval.
-pub fn time_wheel<'a, T: Debug + Send + 'static>(
+///
+/// Use `Builder` for more settings.
+pub fn time_wheel<T: Debug + Send + 'static>(
     interval: Duration,
 ) -> (Scheduler<T>, TickReceiver<T>) {
-    let (sender, receiver) = crossbeam_channel::unbounded();
+    Builder::new(interval).build()
+}
 
-    let entities = Arc::new(Mutex::new(Vec::<(T, SystemTime)>::new()));
-    let interval_in_nanos = interval.as_nanos() as u64;
-    let entities_send = entities.clone();
+/// A bounded channel with `capacity`, or an unbounded one.
+pub(crate) fn channel<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    match capacity {
+        Some(capacity) => crossbeam_channel::bounded(capacity),
+        None => crossbeam_channel::unbounded(),
+    }
+}
 
-    let handler = thread::spawn(move || {
-        let notice = move |entity| {
-            sender
-                .send(entity)
-                .expect("no receiver, stop running timer wheel");
-        };
+/// Start the timer threads, one per shard, expired entities of shard `i` are sent to `senders[i]`.
+///
+/// With `reliable`, the entities are delivered again until they are acked.
+pub(crate) fn start<T, K>(
+    builder: Builder,
+    senders: Vec<Sender<Delivery<T>>>,
+    reliable: Option<Reliable<T>>,
+) -> Scheduler<T, K>
+where
+    T: Debug + Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    let Builder {
+        interval,
+        workers,
+        rounding,
+        capacity,
+        max_pending,
+        tag_limits,
+        block_when_full,
+        jitter,
+        seed,
+        ..
+    } = builder;
 
-        let notice_copy = notice.clone();
+    let topics: Topics<T> = Arc::new(RwLock::new(HashMap::new()));
+    let pool = Arc::new(WorkerPool::new(workers));
+    let admission = (max_pending.is_some() || !tag_limits.is_empty())
+        .then(|| Arc::new(Admission::new(max_pending, tag_limits, block_when_full)));
 
-        let mut wheel = Wheel::<T>::new(notice);
+    let shards = senders
+        .into_iter()
+        .enumerate()
+        .map(|(index, sender)| {
+            let router = Router {
+                topics: topics.clone(),
+                sender,
+                pool: pool.clone(),
+            };
+            Shard::spawn(
+                index,
+                interval,
+                rounding,
+                router,
+                admission.clone(),
+                reliable.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let acker = reliable.is_some().then(|| shard::acker(&shards));
 
-        let start = Instant::now();
-        let start_at = SystemTime::now();
+    Scheduler {
+        shards,
+        next_shard: AtomicUsize::new(0),
+        topics,
+        capacity,
+        pool,
+        admission,
+        acker,
+        jitter,
+        rng: Mutex::new(seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)),
+    }
+}
 
-        thread::sleep(interval);
-        loop {
-            let real_ticks = wheel.ticks as u128;
-            let should_ticks = start.elapsed().as_nanos() / interval_in_nanos as u128;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rounding;
+    use std::thread;
 
-            let one_loop_start = Instant::now();
-            if should_ticks > real_ticks {
-                wheel.tick_to(should_ticks as u64);
-            }
+    #[test]
+    fn test_topic_delivery() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+        let orders = scheduler.subscribe("orders");
+        let sessions = scheduler.subscribe("sessions");
+
+        scheduler
+            .arrange("order")
+            .on("orders")
+            .after(Duration::from_millis(5));
+        scheduler
+            .arrange("session")
+            .on("sessions")
+            .after(Duration::from_millis(5));
+        scheduler.arrange("plain").after(Duration::from_millis(5));
+
+        assert_eq!(orders.recv().unwrap(), "order");
+        assert_eq!(sessions.recv().unwrap(), "session");
+        assert_eq!(receiver.recv().unwrap(), "plain");
+    }
+
+    #[test]
+    fn test_topic_without_subscriber() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        scheduler
+            .arrange("nobody")
+            .on("unknown")
+            .after(Duration::from_millis(5));
+        assert_eq!(receiver.recv().unwrap(), "nobody");
+
+        let dropped = scheduler.subscribe("dropped");
+        drop(dropped);
+        scheduler
+            .arrange("dropped")
+            .on("dropped")
+            .after(Duration::from_millis(5));
+        assert_eq!(receiver.recv().unwrap(), "dropped");
+    }
+
+    #[test]
+    fn test_arrange_fn() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .workers(2)
+            .build::<&str>();
+        let (sender, done) = crossbeam_channel::unbounded();
+
+        scheduler
+            .arrange_fn(|| panic!("job panicked"))
+            .after(Duration::from_millis(2));
+        scheduler
+            .arrange_fn(move || sender.send("job").unwrap())
+            .after(Duration::from_millis(5));
+        scheduler.arrange("entity").after(Duration::from_millis(5));
+
+        assert_eq!(done.recv().unwrap(), "job");
+        assert_eq!(receiver.recv().unwrap(), "entity");
+
+        let mut metrics = scheduler.pool_metrics();
+        while metrics.completed < 2 {
+            thread::sleep(Duration::from_millis(1));
+            metrics = scheduler.pool_metrics();
+        }
+        assert_eq!(metrics.workers, 2);
+        assert_eq!(metrics.panicked, 1);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        let cancelled = scheduler
+            .arrange("cancelled")
+            .after(Duration::from_millis(20));
+        let fired = scheduler.arrange("fired").after(Duration::from_millis(30));
+
+        assert!(scheduler.cancel(cancelled));
+        assert!(!scheduler.cancel(cancelled));
+        assert_eq!(receiver.recv().unwrap(), "fired");
+        assert!(!scheduler.cancel(fired));
+    }
+
+    #[test]
+    fn test_exit_when_dropped() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        scheduler
+            .arrange("pending")
+            .after(Duration::from_millis(20));
+        drop(scheduler);
+
+        // the pending task is still delivered, then the timer thread exits
+        assert_eq!(receiver.recv().unwrap(), "pending");
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn test_shards() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .shards(4)
+            .build::<usize>();
+
+        let ids = (0..8)
+            .map(|i| scheduler.arrange(i).after(Duration::from_millis(20)))
+            .collect::<Vec<_>>();
+        let shards = ids.iter().map(|id| id.shard()).collect::<Vec<_>>();
+        assert_eq!(shards, vec![0, 1, 2, 3, 0, 1, 2, 3]);
+
+        assert!(scheduler.cancel(ids[6]));
+        let mut received = (0..7).map(|_| receiver.recv().unwrap()).collect::<Vec<_>>();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2, 3, 4, 5, 7]);
+
+        let first = scheduler.arrange(0).shard_by("key").after(Duration::ZERO);
+        let second = scheduler.arrange(1).shard_by("key").after(Duration::ZERO);
+        assert_eq!(first.shard(), second.shard());
+    }
+
+    #[test]
+    fn test_per_shard_receivers() {
+        let (scheduler, receivers) = Builder::new(Duration::from_millis(1))
+            .shards(2)
+            .build_per_shard::<usize>();
+
+        for i in 0..4 {
+            scheduler.arrange(i).after(Duration::from_millis(5));
+        }
+        assert_eq!(receivers[0].recv().unwrap() % 2, 0);
+        assert_eq!(receivers[0].recv().unwrap() % 2, 0);
+        assert_eq!(receivers[1].recv().unwrap() % 2, 1);
+        assert_eq!(receivers[1].recv().unwrap() % 2, 1);
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let (scheduler, receiver) = time_wheel::<usize>(Duration::from_millis(1));
+
+        for i in 0..1000 {
+            scheduler.arrange(i).after(Duration::ZERO);
+        }
+        let received = (0..1000)
+            .map(|_| receiver.recv().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(received, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_many_producers() {
+        const PRODUCERS: usize = 8;
+        const COUNT: usize = 1000;
+
+        let (scheduler, receiver) = time_wheel::<(usize, usize)>(Duration::from_millis(1));
+        let scheduler = Arc::new(scheduler);
+
+        let producers = (0..PRODUCERS)
+            .map(|producer| {
+                let scheduler = scheduler.clone();
+                thread::spawn(move || {
+                    for i in 0..COUNT {
+                        scheduler.arrange((producer, i)).after(Duration::ZERO);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // every producer's tasks are received in the order they are arranged
+        let mut next = [0; PRODUCERS];
+        for _ in 0..PRODUCERS * COUNT {
+            let (producer, i) = receiver.recv().unwrap();
+            assert_eq!(next[producer], i);
+            next[producer] += 1;
+        }
+        assert_eq!(next, [COUNT; PRODUCERS]);
+    }
+
+    #[test]
+    fn test_arrange_many() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .shards(3)
+            .build::<usize>();
+
+        let now = SystemTime::now();
+        let ids = scheduler.arrange_many((0..10).map(|i| {
+            let deadline = match i % 2 {
+                0 => Deadline::After(Duration::from_millis(20)),
+                _ => Deadline::At(now + Duration::from_millis(10)),
+            };
+            (i, deadline)
+        }));
+        assert_eq!(ids.len(), 10);
+        let shards = ids.iter().map(|id| id.shard()).collect::<Vec<_>>();
+        assert_eq!(shards, vec![0, 1, 2, 0, 1, 2, 0, 1, 2, 0]);
+
+        assert!(scheduler.cancel(ids[4]));
+        let mut received = (0..9).map(|_| receiver.recv().unwrap()).collect::<Vec<_>>();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2, 3, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_same_deadline_order() {
+        let (scheduler, receiver) = time_wheel::<usize>(Duration::from_millis(1));
 
-            let mut entities = entities_send.lock().unwrap();
-            while let Some((entity, when)) = entities.pop() {
-                let pure_time_offset = when
-                    .duration_since(start_at)
-                    .unwrap_or_default()
-                    .checked_sub(start.elapsed())
-                    .unwrap_or_default()
-                    .as_nanos();
-
-                if pure_time_offset > interval_in_nanos as u128 {
-                    let offset = pure_time_offset / interval_in_nanos as u128;
-                    wheel.schedule(entity, offset as u64, when);
-                } else {
-                    notice_copy(entity);
-                }
+        let when = SystemTime::now() + Duration::from_millis(100);
+        let earlier = when - Duration::from_millis(30);
+        for i in 0..500 {
+            scheduler.arrange(i).at(when);
+        }
+        thread::sleep(Duration::from_millis(10));
+        scheduler.arrange_many((500..1000).map(|i| (i, when)));
+        scheduler.arrange(1000).at(earlier);
+
+        assert_eq!(receiver.recv().unwrap(), 1000);
+        let received = (0..1000)
+            .map(|_| receiver.recv().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(received, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_never_early() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(3))
+            .rounding(Rounding::NeverEarly)
+            .build::<SystemTime>();
+
+        let now = SystemTime::now();
+        for i in 0..50 {
+            let when = now + Duration::from_micros(i * 997);
+            scheduler.arrange(when).at(when);
+        }
+        for _ in 0..50 {
+            let when = receiver.recv().unwrap();
+            assert!(SystemTime::now() >= when);
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        let when = SystemTime::now() + Duration::from_millis(20);
+        scheduler.arrange("bulk-1").at(when);
+        scheduler.arrange("bulk-2").at(when);
+        scheduler.arrange("health").priority(9).at(when);
+
+        assert_eq!(receiver.recv().unwrap(), "health");
+        assert_eq!(receiver.recv().unwrap(), "bulk-1");
+        assert_eq!(receiver.recv().unwrap(), "bulk-2");
+    }
+
+    #[test]
+    fn test_priority_bounded() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .bounded(1)
+            .build::<&str>();
+
+        scheduler.arrange("bulk-1").after(Duration::ZERO);
+        scheduler.arrange("bulk-2").after(Duration::ZERO);
+        scheduler.arrange("bulk-3").after(Duration::ZERO);
+        thread::sleep(Duration::from_millis(20));
+        scheduler
+            .arrange("health")
+            .priority(9)
+            .after(Duration::ZERO);
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(receiver.recv().unwrap(), "bulk-1");
+        assert_eq!(receiver.recv().unwrap(), "health");
+        assert_eq!(receiver.recv().unwrap(), "bulk-2");
+        assert_eq!(receiver.recv().unwrap(), "bulk-3");
+    }
+
+    #[test]
+    fn test_keyed_rearm() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .shards(2)
+            .build_keyed::<&str, &str>();
+
+        scheduler
+            .arrange_keyed("session-1", "first")
+            .after(Duration::from_millis(10));
+        scheduler
+            .arrange_keyed("session-1", "second")
+            .after(Duration::from_millis(40));
+        scheduler
+            .arrange_keyed("session-2", "other")
+            .after(Duration::from_millis(20));
+
+        let start = SystemTime::now();
+        assert_eq!(receiver.recv().unwrap(), "other");
+        assert_eq!(receiver.recv().unwrap(), "second");
+        assert!(start.elapsed().unwrap() >= Duration::from_millis(20));
+
+        // the key is free again once it is delivered
+        assert!(!scheduler.cancel_key(&"session-1"));
+        scheduler
+            .arrange_keyed("session-1", "third")
+            .after(Duration::from_millis(5));
+        assert_eq!(receiver.recv().unwrap(), "third");
+    }
+
+    #[test]
+    fn test_cancel_key() {
+        let (scheduler, receiver) =
+            Builder::new(Duration::from_millis(1)).build_keyed::<u32, u32>();
+
+        let id = scheduler
+            .arrange_keyed(1, 1)
+            .after(Duration::from_millis(20));
+        scheduler
+            .arrange_keyed(2, 2)
+            .after(Duration::from_millis(30));
+
+        assert!(scheduler.cancel_key(&1));
+        assert!(!scheduler.cancel_key(&1));
+        assert!(!scheduler.cancel(id));
+        assert_eq!(receiver.recv().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_tags() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .shards(2)
+            .build::<&str>();
+
+        for entity in ["a-1", "a-2", "a-3"] {
+            scheduler
+                .arrange(entity)
+                .tag("tenant-a")
+                .after(Duration::from_millis(30));
+        }
+        scheduler
+            .arrange("b-1")
+            .tag("tenant-b")
+            .tag("slow")
+            .after(Duration::from_millis(10));
+        scheduler
+            .arrange("b-2")
+            .tag("tenant-b")
+            .after(Duration::from_millis(20));
+
+        assert_eq!(scheduler.count_tag("tenant-a"), 3);
+        assert_eq!(scheduler.count_tag("tenant-b"), 2);
+        assert_eq!(scheduler.count_tag("unknown"), 0);
+
+        let mut cancelled = scheduler.cancel_tag("tenant-a");
+        cancelled.sort();
+        assert_eq!(cancelled, vec!["a-1", "a-2", "a-3"]);
+        assert_eq!(scheduler.count_tag("tenant-a"), 0);
+
+        // b-1 goes after b-2 now
+        assert_eq!(scheduler.shift_tag("slow", Duration::from_millis(30)), 1);
+        assert_eq!(receiver.recv().unwrap(), "b-2");
+        assert_eq!(scheduler.count_tag("tenant-b"), 1);
+        assert_eq!(receiver.recv().unwrap(), "b-1");
+        assert_eq!(scheduler.count_tag("slow"), 0);
+        assert!(scheduler.cancel_tag("tenant-b").is_empty());
+    }
+
+    #[test]
+    fn test_query_pending() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        let when = SystemTime::now() + Duration::from_millis(30);
+        let soon = scheduler.arrange("soon").at(when);
+        // far enough to sit in a higher level
+        let later = scheduler.arrange("later").after(Duration::from_secs(3600));
+
+        assert_eq!(scheduler.deadline(soon), Some(when));
+        assert!(scheduler.is_pending(soon));
+        let remaining = scheduler.remaining(later).unwrap();
+        assert!(remaining > Duration::from_secs(3590) && remaining <= Duration::from_secs(3600));
+
+        assert_eq!(receiver.recv().unwrap(), "soon");
+        assert!(!scheduler.is_pending(soon));
+        assert_eq!(scheduler.deadline(soon), None);
+        assert_eq!(scheduler.remaining(soon), None);
+
+        assert!(scheduler.cancel(later));
+        assert!(!scheduler.is_pending(later));
+    }
+
+    #[test]
+    fn test_reschedule() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        let now = SystemTime::now();
+        let first = scheduler
+            .arrange("first")
+            .at(now + Duration::from_secs(3600));
+        let second = scheduler
+            .arrange("second")
+            .at(now + Duration::from_millis(20));
+
+        // earlier, from a higher level
+        let when = now + Duration::from_millis(10);
+        assert!(scheduler.reschedule(first, when));
+        assert_eq!(scheduler.deadline(first), Some(when));
+        assert_eq!(receiver.recv_with_id().unwrap(), (first, "first"));
+        assert!(!scheduler.reschedule(first, when));
+
+        // later, it keeps its id
+        let when = now + Duration::from_secs(60);
+        assert!(scheduler.reschedule(second, when));
+        assert_eq!(scheduler.deadline(second), Some(when));
+        assert!(scheduler.cancel(second));
+    }
+
+    #[test]
+    fn test_rrule() {
+        let (scheduler, receiver) = time_wheel::<&str>(Duration::from_millis(1));
+
+        // the first occurrence is past, the others are a minute apart
+        let rule = "FREQ=MINUTELY;COUNT=3".parse::<RRule>().unwrap();
+        let rule = rule.dtstart(SystemTime::now() - Duration::from_secs(30));
+        let occurrences = rule.occurrences().skip(1).collect::<Vec<_>>();
+        let id = scheduler.arrange("standup").rrule(rule);
+        assert_eq!(scheduler.deadline(id), Some(occurrences[0]));
+
+        // delivered early, then back at the next occurrence under the same id
+        let soon = SystemTime::now() + Duration::from_millis(10);
+        assert!(scheduler.reschedule(id, soon));
+        assert_eq!(receiver.recv_with_id().unwrap(), (id, "standup"));
+        assert_eq!(scheduler.deadline(id), Some(occurrences[1]));
+        assert_eq!(scheduler.pending_count(), 1);
+
+        let soon = SystemTime::now() + Duration::from_millis(10);
+        assert!(scheduler.reschedule(id, soon));
+        assert_eq!(receiver.recv_with_id().unwrap(), (id, "standup"));
+        assert_eq!(scheduler.pending_count(), 0);
+
+        let rule = "FREQ=DAILY".parse::<RRule>().unwrap();
+        let id = scheduler.arrange("daily").rrule(rule);
+        assert!(scheduler.cancel(id));
+        assert_eq!(scheduler.pending_count(), 0);
+
+        let over = "DTSTART:20200101T000000Z\nRRULE:FREQ=DAILY;UNTIL=20200201";
+        let result = scheduler.arrange("over").try_rrule(over.parse().unwrap());
+        assert!(matches!(result, Err(TimerError::InvalidRule(_))));
+
+        // a job can not run at every occurrence
+        let rule = "FREQ=DAILY".parse::<RRule>().unwrap();
+        let result = scheduler.arrange_fn(|| ()).try_rrule(rule);
+        assert!(matches!(result, Err(TimerError::InvalidRule(_))));
+        assert_eq!(scheduler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .shards(2)
+            .build::<&str>();
+        assert_eq!(scheduler.pending_count(), 0);
+        assert_eq!(scheduler.next_deadline(), None);
+
+        let now = SystemTime::now();
+        let first = now + Duration::from_millis(20);
+        let second = now + Duration::from_secs(60);
+        let third = now + Duration::from_secs(7200);
+        let c = scheduler.arrange("c").at(third);
+        let a = scheduler.arrange("a").at(first);
+        let b = scheduler.arrange("b").at(second);
+        scheduler.arrange_fn(|| {}).at(second);
+        // the timer of a token is not a task
+        let _token = scheduler.deadline_token(Duration::from_millis(10));
+
+        assert_eq!(scheduler.pending_count(), 4);
+        assert_eq!(scheduler.next_deadline(), Some(first));
+        assert_eq!(
+            scheduler.snapshot(),
+            vec![(a, first, "a"), (b, second, "b"), (c, third, "c")]
+        );
+
+        assert_eq!(receiver.recv().unwrap(), "a");
+        assert_eq!(scheduler.pending_count(), 3);
+        assert_eq!(scheduler.next_deadline(), Some(second));
+    }
+
+    #[test]
+    fn test_capacity_limits() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .max_pending(3)
+            .tag_limit("tenant", 1)
+            .build::<&str>();
+
+        scheduler
+            .arrange("t-1")
+            .tag("tenant")
+            .after(Duration::from_millis(10));
+        assert!(matches!(
+            scheduler
+                .arrange("t-2")
+                .tag("tenant")
+                .try_after(Duration::from_millis(10)),
+            Err(TimerError::CapacityExceeded(_))
+        ));
+        let id = scheduler.arrange("a").after(Duration::from_secs(60));
+        assert!(scheduler
+            .try_arrange_many([
+                ("b", Duration::from_secs(60)),
+                ("c", Duration::from_secs(60))
+            ])
+            .is_err());
+        scheduler.arrange("b").after(Duration::from_secs(60));
+        assert!(scheduler
+            .arrange("c")
+            .try_after(Duration::from_millis(10))
+            .is_err());
+        assert_eq!(
+            scheduler.admission_metrics(),
+            AdmissionMetrics {
+                pending: 3,
+                rejected: 4
             }
-            mem::drop(entities);
+        );
 
-            let next_ticks = wheel.next_ticks();
+        // expired and cancelled tasks make room
+        assert_eq!(receiver.recv().unwrap(), "t-1");
+        assert!(scheduler.cancel(id));
+        assert_eq!(scheduler.admission_metrics().pending, 1);
+        scheduler
+            .arrange("t-3")
+            .tag("tenant")
+            .after(Duration::from_millis(5));
+        assert_eq!(receiver.recv().unwrap(), "t-3");
+    }
 
-            let next_tick_time = interval_in_nanos * next_ticks as u64;
-            let process_time = one_loop_start.elapsed().as_nanos() as u64;
+    #[test]
+    fn test_rearm_when_full() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .max_pending(1)
+            .tag_limit("tenant", 1)
+            .build_keyed::<&str, &str>();
 
-            if next_tick_time > process_time {
-                thread::park_timeout(Duration::from_nanos(next_tick_time - process_time));
+        scheduler
+            .arrange_keyed("k", "first")
+            .tag("tenant")
+            .after(Duration::from_secs(60));
+        assert!(scheduler
+            .arrange("other")
+            .try_after(Duration::from_millis(10))
+            .is_err());
+        // replacing the pending task of the key takes over its room
+        let id = scheduler
+            .arrange_keyed("k", "second")
+            .tag("tenant")
+            .try_after(Duration::from_millis(10))
+            .unwrap();
+        assert!(scheduler
+            .arrange_keyed("l", "third")
+            .try_after(Duration::from_millis(10))
+            .is_err());
+        assert_eq!(
+            scheduler.admission_metrics(),
+            AdmissionMetrics {
+                pending: 1,
+                rejected: 2
             }
+        );
+
+        assert_eq!(receiver.recv_with_id().unwrap(), (id, "second"));
+        assert_eq!(scheduler.pending_count(), 0);
+        assert_eq!(scheduler.admission_metrics().pending, 0);
+    }
+
+    #[test]
+    fn test_block_when_full() {
+        let (scheduler, receiver) = Builder::new(Duration::from_millis(1))
+            .max_pending(1)
+            .block_when_full(Duration::from_secs(1))
+            .build::<&str>();
+
+        scheduler.arrange("first").after(Duration::from_millis(20));
+        // waits for the first one to expire
+        scheduler.arrange("second").after(Duration::ZERO);
+        assert_eq!(receiver.recv().unwrap(), "first");
+        assert_eq!(receiver.recv().unwrap(), "second");
+        assert_eq!(scheduler.admission_metrics().rejected, 0);
+    }
+
+    #[test]
+    fn test_reliable() {
+        let (scheduler, receiver, dead_letters) = Builder::new(Duration::from_millis(1))
+            .visibility_timeout(Duration::from_millis(20))
+            .max_redeliveries(1)
+            .build_reliable::<&str>();
+
+        let acked = scheduler.arrange("acked").after(Duration::from_millis(5));
+        let lost = scheduler.arrange("lost").after(Duration::from_millis(5));
+        assert!(!receiver.ack(acked));
+
+        assert_eq!(receiver.recv_with_id().unwrap(), (acked, "acked"));
+        assert_eq!(receiver.recv_with_id().unwrap(), (lost, "lost"));
+        assert!(receiver.ack(acked));
+        assert!(!receiver.ack(acked));
+        assert!(scheduler.is_pending(lost));
+
+        // redelivered once, then given up
+        assert_eq!(receiver.recv_with_id().unwrap(), (lost, "lost"));
+        assert_eq!(dead_letters.recv_with_id().unwrap(), (lost, "lost"));
+        assert!(!receiver.ack(lost));
+        assert!(!scheduler.is_pending(lost));
+        assert_eq!(scheduler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let build = || {
+            Builder::new(Duration::from_millis(1))
+                .jitter(Jitter::Uniform(Duration::from_secs(10)))
+                .jitter_seed(42)
+                .build::<usize>()
+                .0
+        };
+        let deadlines = |scheduler: &Scheduler<usize>| {
+            let now = SystemTime::now();
+            (0..10)
+                .map(|i| {
+                    let id = scheduler.arrange(i).after(Duration::from_secs(60));
+                    scheduler.deadline(id).unwrap().duration_since(now).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let scheduler = build();
+        let first = deadlines(&scheduler);
+        assert!(first
+            .iter()
+            .all(|delay| *delay >= Duration::from_secs(60) && *delay <= Duration::from_secs(71)));
+        let mut distinct = first.iter().map(Duration::as_secs).collect::<Vec<_>>();
+        distinct.dedup();
+        assert!(distinct.len() > 1);
+
+        // the same seed gives the same jitter
+        let second = deadlines(&build());
+        for (first, second) in first.iter().zip(&second) {
+            assert!((first.as_secs_f64() - second.as_secs_f64()).abs() < 0.5);
         }
-    });
 
-    (Scheduler { handler, entities }, TickReceiver(receiver))
+        let id = scheduler
+            .arrange(0)
+            .after_jittered(Duration::ZERO, Jitter::Exponential(Duration::from_secs(1)));
+        assert!(scheduler.is_pending(id));
+        let when = SystemTime::now() + Duration::from_secs(60);
+        let id = scheduler.arrange(0).at(when);
+        assert_eq!(scheduler.deadline(id), Some(when));
+    }
 }
@@ -0,0 +1,192 @@
+use std::{cmp::Ordering, collections::BinaryHeap, sync::Arc};
+
+use crossbeam_channel::{Sender, TrySendError};
+
+use super::{Delivery, Envelope, Payload, Topics};
+use crate::pool::WorkerPool;
+use crate::TimerId;
+
+/// Sends the expired payloads to where they belong.
+pub(super) struct Router<T> {
+    pub(super) topics: Topics<T>,
+    pub(super) sender: Sender<Delivery<T>>,
+    pub(super) pool: Arc<WorkerPool>,
+}
+
+impl<T> Router<T> {
+    /// Send `entity` to the subscriber of `topic` or to the default receiver, give it back if the receiver is full.
+    fn try_send(&self, entity: Delivery<T>, topic: Option<&String>) -> Result<(), Delivery<T>> {
+        // a topic without (alive) subscriber falls back to the default receiver
+        let subscriber = topic.and_then(|topic| self.topics.read().unwrap().get(topic).cloned());
+        let entity = match subscriber {
+            Some(subscriber) => match subscriber.try_send(entity) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(entity)) => return Err(entity),
+                Err(TrySendError::Disconnected(entity)) => entity,
+            },
+            None => entity,
+        };
+
+        match self.sender.try_send(entity) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(entity)) => Err(entity),
+            Err(TrySendError::Disconnected(_)) => panic!("no receiver, stop running timer wheel"),
+        }
+    }
+}
+
+/// An expired entity waiting for room in its receiver.
+struct Pending<T> {
+    priority: u8,
+    /// The order it is expired in.
+    order: u64,
+    entity: Delivery<T>,
+    topic: Option<String>,
+}
+
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Pending<T> {}
+
+impl<T> PartialOrd for Pending<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Pending<T> {
+    /// Higher priority first, then the earlier expired.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+/// Expired entities of a timer thread, sent by priority.
+///
+/// With bounded receivers, the entities wait here while the receivers are full,
+/// the higher priorities expired later still go first.
+pub(super) struct Outbox<T> {
+    pending: BinaryHeap<Pending<T>>,
+    order: u64,
+}
+
+impl<T> Outbox<T> {
+    pub(super) fn new() -> Self {
+        Outbox {
+            pending: BinaryHeap::new(),
+            order: 0,
+        }
+    }
+
+    /// Run jobs and wake tasks right away, queue entities for `flush`.
+    pub(super) fn push<K>(&mut self, id: u64, envelope: Envelope<T, K>, router: &Router<T>) {
+        let Envelope {
+            payload,
+            topic,
+            priority,
+            ..
+        } = envelope;
+
+        match payload {
+            Payload::Job(job) => router.pool.execute(job),
+            Payload::Alarm(job) => job(),
+            #[cfg(feature = "async")]
+            Payload::Wake(waker) => waker.wake(),
+            Payload::Entity(entity) => {
+                self.order += 1;
+                self.pending.push(Pending {
+                    priority,
+                    order: self.order,
+                    entity: (TimerId::from_u64(id), entity),
+                    topic,
+                });
+            }
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Send as many entities as the receivers can take.
+    pub(super) fn flush(&mut self, router: &Router<T>) {
+        let mut blocked = Vec::new();
+        // once a receiver is full, the lower priorities of it must wait too
+        let mut full_topics = Vec::<Option<String>>::new();
+
+        while let Some(pending) = self.pending.pop() {
+            if full_topics.contains(&pending.topic) {
+                blocked.push(pending);
+                continue;
+            }
+            let Pending {
+                priority,
+                order,
+                entity,
+                topic,
+            } = pending;
+
+            if let Err(entity) = router.try_send(entity, topic.as_ref()) {
+                full_topics.push(topic.clone());
+                blocked.push(Pending {
+                    priority,
+                    order,
+                    entity,
+                    topic,
+                });
+            }
+        }
+
+        self.pending.extend(blocked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::RwLock};
+
+    fn envelope(entity: u32, priority: u8) -> Envelope<u32, ()> {
+        Envelope {
+            payload: Payload::Entity(entity),
+            topic: None,
+            priority,
+            key: None,
+            tags: Vec::new(),
+            attempts: 0,
+            recurrence: None,
+        }
+    }
+
+    #[test]
+    fn test_priority_under_backpressure() {
+        let (sender, receiver) = crossbeam_channel::bounded(2);
+        let router = Router {
+            topics: Arc::new(RwLock::new(HashMap::new())),
+            sender,
+            pool: Arc::new(WorkerPool::new(1)),
+        };
+        let mut outbox = Outbox::new();
+
+        outbox.push(1, envelope(1, 0), &router);
+        outbox.push(2, envelope(2, 0), &router);
+        outbox.push(3, envelope(3, 0), &router);
+        outbox.flush(&router);
+        assert!(!outbox.is_empty());
+
+        // expired later, but goes first
+        outbox.push(4, envelope(4, 9), &router);
+        assert_eq!(receiver.recv().unwrap().1, 1);
+        assert_eq!(receiver.recv().unwrap().1, 2);
+        outbox.flush(&router);
+        assert!(outbox.is_empty());
+        assert_eq!(receiver.recv().unwrap().1, 4);
+        assert_eq!(receiver.recv().unwrap().1, 3);
+    }
+}
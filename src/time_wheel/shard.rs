@@ -0,0 +1,870 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use super::outbox::{Outbox, Router};
+use super::{Acker, Envelope, Payload, Reliable};
+use crate::admission::Admission;
+use crate::core::{Clock, Entity, Wheel};
+use crate::{Rounding, TimerId};
+
+/// Requests handled by the timer thread, in the order they are sent.
+enum Command<T, K> {
+    Arrange(u64, Envelope<T, K>, SystemTime),
+    /// Arrange a keyed task in the room of the pending one of its key, the envelope is given back if it can not.
+    Replace(
+        u64,
+        Envelope<T, K>,
+        SystemTime,
+        Sender<Result<(), Envelope<T, K>>>,
+    ),
+    ArrangeMany(Vec<(u64, Envelope<T, K>, SystemTime)>),
+    Cancel(u64, Sender<bool>),
+    CancelKey(K, Sender<bool>),
+    CancelTag(String, Sender<Vec<T>>),
+    CountTag(String, Sender<usize>),
+    ShiftTag(String, Duration, Sender<usize>),
+    Reschedule(u64, SystemTime, Sender<bool>),
+    Deadline(u64, Sender<Option<SystemTime>>),
+    Ack(u64, Sender<bool>),
+    /// Run a closure on the wheel, it replies by itself.
+    Inspect(Inspection<T, K>),
+    /// Exit the timer thread, dropping the pending tasks.
+    Shutdown,
+}
+
+type Inspection<T, K> = Box<dyn FnOnce(&Wheel<Envelope<T, K>>) + Send>;
+
+/// Where the ticks of a shard are counted from, shared with its alarms.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Epoch {
+    /// The system time of tick 0, deadlines are mapped to ticks from it.
+    start_at: SystemTime,
+    /// The instant of tick 0, ticks are counted on the monotonic clock from it.
+    start: Instant,
+    interval: Duration,
+}
+
+impl Epoch {
+    fn new(interval: Duration) -> Self {
+        // the system time first, tick `n` is never earlier than `start_at + n * interval`
+        let start_at = SystemTime::now();
+        Epoch {
+            start_at,
+            start: Instant::now(),
+            interval,
+        }
+    }
+
+    /// The tick due now, the timer thread is at it or about to move to it.
+    pub(super) fn ticks(&self) -> u64 {
+        (self.start.elapsed().as_nanos() / self.interval.as_nanos().max(1)) as u64
+    }
+
+    /// The system time `elapsed` after tick 0.
+    pub(super) fn time_of(&self, elapsed: Duration) -> SystemTime {
+        self.start_at + elapsed
+    }
+
+    pub(super) fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// One timer thread with its own wheel.
+///
+/// Requests go through a lock-free channel, so arranging never waits for a tick in progress,
+/// and sending to the channel wakes the timer thread only when it is waiting.
+pub(super) struct Shard<T, K> {
+    index: usize,
+    inbox: Sender<Command<T, K>>,
+    /// Shared by the clones, so they never reuse an id.
+    sequence: Arc<AtomicU64>,
+    epoch: Epoch,
+}
+
+impl<T, K> Clone for Shard<T, K> {
+    fn clone(&self) -> Self {
+        Shard {
+            index: self.index,
+            inbox: self.inbox.clone(),
+            sequence: self.sequence.clone(),
+            epoch: self.epoch,
+        }
+    }
+}
+
+impl<T, K> Shard<T, K>
+where
+    T: Debug + Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    pub(super) fn spawn(
+        index: usize,
+        interval: Duration,
+        rounding: Rounding,
+        router: Router<T>,
+        admission: Option<Arc<Admission>>,
+        reliable: Option<Reliable<T>>,
+    ) -> Self {
+        let (inbox, commands) = crossbeam_channel::unbounded();
+        let epoch = Epoch::new(interval);
+
+        thread::Builder::new()
+            .name(format!("xpd-timer-{}", index))
+            .spawn(move || run(epoch, rounding, commands, router, admission, reliable))
+            .expect("failed to spawn timer thread");
+
+        Shard {
+            index,
+            inbox,
+            sequence: Arc::new(AtomicU64::new(0)),
+            epoch,
+        }
+    }
+}
+
+impl<T, K> Shard<T, K> {
+    pub(super) fn epoch(&self) -> &Epoch {
+        &self.epoch
+    }
+
+    pub(super) fn arrange(&self, envelope: Envelope<T, K>, when: SystemTime) -> TimerId {
+        let id = TimerId::new(self.index, self.sequence.fetch_add(1, Ordering::Relaxed));
+
+        // the timer thread is alive while a shard is, unless its receiver is dropped, nobody waits for the task
+        let _ = self
+            .inbox
+            .send(Command::Arrange(id.as_u64(), envelope, when));
+
+        id
+    }
+
+    /// Arrange a keyed task in the admitted room of the pending task of its key,
+    /// the envelope is given back if there is none or a limit of its other tags is reached.
+    pub(super) fn replace(
+        &self,
+        envelope: Envelope<T, K>,
+        when: SystemTime,
+    ) -> Result<TimerId, Envelope<T, K>> {
+        let id = TimerId::new(self.index, self.sequence.fetch_add(1, Ordering::Relaxed));
+
+        // if the timer thread exited, nobody waits for the task like `arrange`
+        self.request(|reply| Command::Replace(id.as_u64(), envelope, when, reply))
+            .unwrap_or(Ok(()))
+            .map(|_| id)
+    }
+
+    pub(super) fn arrange_many(&self, batch: Vec<(Envelope<T, K>, SystemTime)>) -> Vec<TimerId> {
+        if batch.is_empty() {
+            return Vec::new();
+        }
+
+        let first = self
+            .sequence
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        let ids = (first..first + batch.len() as u64)
+            .map(|sequence| TimerId::new(self.index, sequence))
+            .collect::<Vec<_>>();
+        let batch = ids
+            .iter()
+            .zip(batch)
+            .map(|(id, (envelope, when))| (id.as_u64(), envelope, when))
+            .collect();
+
+        let _ = self.inbox.send(Command::ArrangeMany(batch));
+
+        ids
+    }
+
+    pub(super) fn cancel(&self, id: TimerId) -> bool {
+        self.request(|reply| Command::Cancel(id.as_u64(), reply))
+            .unwrap_or(false)
+    }
+
+    /// Cancel without waiting for the reply.
+    pub(super) fn discard(&self, id: TimerId) {
+        let (reply, _) = crossbeam_channel::bounded(1);
+        let _ = self.inbox.send(Command::Cancel(id.as_u64(), reply));
+    }
+
+    /// Stop the timer thread without waiting for it.
+    pub(super) fn shutdown(&self) {
+        let _ = self.inbox.send(Command::Shutdown);
+    }
+
+    pub(super) fn cancel_key(&self, key: K) -> bool {
+        self.request(|reply| Command::CancelKey(key, reply))
+            .unwrap_or(false)
+    }
+
+    pub(super) fn cancel_tag(&self, tag: &str) -> Vec<T> {
+        self.request(|reply| Command::CancelTag(tag.to_string(), reply))
+            .unwrap_or_default()
+    }
+
+    pub(super) fn count_tag(&self, tag: &str) -> usize {
+        self.request(|reply| Command::CountTag(tag.to_string(), reply))
+            .unwrap_or(0)
+    }
+
+    pub(super) fn shift_tag(&self, tag: &str, delay: Duration) -> usize {
+        self.request(|reply| Command::ShiftTag(tag.to_string(), delay, reply))
+            .unwrap_or(0)
+    }
+
+    pub(super) fn reschedule(&self, id: TimerId, when: SystemTime) -> bool {
+        self.request(|reply| Command::Reschedule(id.as_u64(), when, reply))
+            .unwrap_or(false)
+    }
+
+    /// Deadline of a pending task, `None` if it has expired or been cancelled.
+    pub(super) fn deadline(&self, id: TimerId) -> Option<SystemTime> {
+        self.request(|reply| Command::Deadline(id.as_u64(), reply))
+            .flatten()
+    }
+
+    /// Number of pending tasks, the internal timers are left out.
+    pub(super) fn pending_count(&self) -> usize
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.inspect(|wheel| {
+            wheel
+                .entities()
+                .filter(|entity| entity.data.payload.is_task())
+                .count()
+        })
+        .unwrap_or(0)
+    }
+
+    /// Number of pending timers, the internal ones included.
+    #[cfg(test)]
+    pub(super) fn timer_count(&self) -> usize
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.inspect(|wheel| wheel.len()).unwrap_or(0)
+    }
+
+    /// The earliest deadline of the pending tasks, the internal timers are left out.
+    pub(super) fn next_deadline(&self) -> Option<SystemTime>
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.inspect(|wheel| {
+            wheel
+                .entities()
+                .filter(|entity| entity.data.payload.is_task())
+                .map(|entity| entity.when)
+                .min()
+        })
+        .flatten()
+    }
+
+    /// Pending entities with their ids and deadlines, jobs are left out.
+    pub(super) fn snapshot(&self) -> Vec<(TimerId, SystemTime, T)>
+    where
+        T: Clone + Send + 'static,
+        K: 'static,
+    {
+        self.inspect(|wheel| {
+            wheel
+                .entities()
+                .filter_map(|entity| match &entity.data.payload {
+                    Payload::Entity(data) => {
+                        Some((TimerId::from_u64(entity.id), entity.when, data.clone()))
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Run `f` on the wheel in the timer thread, `None` if it exited.
+    fn inspect<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Wheel<Envelope<T, K>>) -> R + Send + 'static,
+    ) -> Option<R>
+    where
+        T: 'static,
+        K: 'static,
+    {
+        self.request(|reply| {
+            Command::Inspect(Box::new(move |wheel| {
+                let _ = reply.send(f(wheel));
+            }))
+        })
+    }
+
+    /// Send a command and wait for the reply of the timer thread, `None` if it exited.
+    fn request<R>(&self, command: impl FnOnce(Sender<R>) -> Command<T, K>) -> Option<R> {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        self.inbox.send(command(sender)).ok()?;
+        receiver.recv().ok()
+    }
+}
+
+/// Acks the delivered tasks of `shards`, by the shard of the id.
+pub(super) fn acker<T, K>(shards: &[Shard<T, K>]) -> Acker
+where
+    T: Send + 'static,
+    K: Send + 'static,
+{
+    let inboxes = shards
+        .iter()
+        .map(|shard| shard.inbox.clone())
+        .collect::<Vec<_>>();
+    Arc::new(move |id: TimerId| {
+        let inbox = match inboxes.get(id.shard()) {
+            Some(inbox) => inbox,
+            None => return false,
+        };
+        let (reply, receiver) = crossbeam_channel::bounded(1);
+        inbox.send(Command::Ack(id.as_u64(), reply)).is_ok() && receiver.recv().unwrap_or(false)
+    })
+}
+
+/// Entities delivered but still pending, waiting to be put back at their redelivery or next occurrence.
+type Rearms<T, K> = Rc<RefCell<Vec<(u64, Envelope<T, K>, SystemTime)>>>;
+
+/// Pending tasks by key and by tag, so they are found without scanning the wheel.
+struct Index<K> {
+    keys: HashMap<K, u64>,
+    tags: HashMap<String, HashSet<u64>>,
+}
+
+impl<K: Hash + Eq + Clone> Index<K> {
+    fn new() -> Self {
+        Index {
+            keys: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Index a task, return the pending task of the same key which is replaced.
+    fn insert<T>(&mut self, id: u64, envelope: &Envelope<T, K>) -> Option<u64> {
+        for tag in &envelope.tags {
+            self.tags.entry(tag.clone()).or_default().insert(id);
+        }
+        let key = envelope.key.as_ref()?;
+        self.keys.insert(key.clone(), id)
+    }
+
+    fn remove<T>(&mut self, id: u64, envelope: &Envelope<T, K>) {
+        for tag in &envelope.tags {
+            if let Some(ids) = self.tags.get_mut(tag) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+        if let Some(key) = &envelope.key {
+            // the key may be arranged again before this one is delivered
+            if self.keys.get(key) == Some(&id) {
+                self.keys.remove(key);
+            }
+        }
+    }
+
+    /// Ids of the pending tasks of `tag`, in the order they are arranged.
+    fn tagged(&self, tag: &str) -> Vec<u64> {
+        let mut ids = self
+            .tags
+            .get(tag)
+            .map(|ids| ids.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// The wheel of a timer thread with the index beside it.
+struct Driver<T, K> {
+    wheel: Wheel<Envelope<T, K>>,
+    clock: Clock,
+    index: Rc<RefCell<Index<K>>>,
+    admission: Option<Arc<Admission>>,
+    rearms: Rearms<T, K>,
+}
+
+impl<T: Debug + 'static, K: Hash + Eq + Clone + 'static> Driver<T, K> {
+    fn new(
+        clock: Clock,
+        admission: Option<Arc<Admission>>,
+        reliable: Option<Reliable<T>>,
+        notice: impl Fn(u64, Envelope<T, K>) + 'static,
+    ) -> Self {
+        let index = Rc::new(RefCell::new(Index::new()));
+        let rearms = Rearms::default();
+
+        let notice = {
+            let index = index.clone();
+            let admission = admission.clone();
+            let rearms = rearms.clone();
+            move |entity: Entity<Envelope<T, K>>| {
+                let (id, mut envelope) = (entity.id, entity.data);
+                if let (Some(recurrence), Payload::Entity(entity)) =
+                    (&mut envelope.recurrence, &envelope.payload)
+                {
+                    if let Some(next) = recurrence.occurrences.next() {
+                        // deliver a copy, the entity stays pending until its last occurrence
+                        let copy = Envelope {
+                            payload: Payload::Entity((recurrence.cloner)(entity)),
+                            topic: envelope.topic.clone(),
+                            priority: envelope.priority,
+                            key: None,
+                            tags: Vec::new(),
+                            attempts: 0,
+                            recurrence: None,
+                        };
+                        rearms.borrow_mut().push((id, envelope, next));
+                        return notice(id, copy);
+                    }
+                }
+
+                if let (Some(reliable), Payload::Entity(entity)) = (&reliable, &envelope.payload) {
+                    if envelope.attempts <= reliable.max_redeliveries {
+                        // deliver a copy, the entity stays pending until it is acked
+                        let copy = Envelope {
+                            payload: Payload::Entity((reliable.cloner)(entity)),
+                            topic: envelope.topic.clone(),
+                            priority: envelope.priority,
+                            key: None,
+                            tags: Vec::new(),
+                            attempts: envelope.attempts,
+                            recurrence: None,
+                        };
+                        envelope.attempts += 1;
+                        let when = SystemTime::now() + reliable.visibility_timeout;
+                        rearms.borrow_mut().push((id, envelope, when));
+                        return notice(id, copy);
+                    }
+                }
+
+                index.borrow_mut().remove(id, &envelope);
+                if let Some(admission) = admission.as_ref().filter(|_| envelope.payload.is_task()) {
+                    admission.release(&envelope.tags);
+                }
+                match (&reliable, envelope.payload) {
+                    (Some(reliable), Payload::Entity(entity)) => {
+                        log::warn!(
+                            "timer task {} is not acked, move it to the dead letters",
+                            id
+                        );
+                        let _ = reliable.dead_letters.send((TimerId::from_u64(id), entity));
+                    }
+                    (_, payload) => notice(
+                        id,
+                        Envelope {
+                            payload,
+                            ..envelope
+                        },
+                    ),
+                }
+            }
+        };
+
+        Driver {
+            wheel: Wheel::with_clock(notice, clock),
+            clock,
+            index,
+            admission,
+            rearms,
+        }
+    }
+
+    /// Handle the commands sent before the tick `ticks`, then move to it,
+    /// so a task cancelled, acked or replaced at its deadline is not delivered.
+    /// `false` on `Command::Shutdown`, the commands after it are dropped.
+    fn step(&mut self, ticks: u64, commands: impl IntoIterator<Item = Command<T, K>>) -> bool {
+        for command in commands {
+            if let Command::Shutdown = command {
+                return false;
+            }
+            self.handle(command);
+        }
+        if ticks > self.wheel.ticks {
+            self.wheel.tick_to(ticks);
+        }
+        self.flush();
+        true
+    }
+
+    /// Notice the expired entities, then put back the ones still pending,
+    /// the delivered ones of reliable mode and the recurring ones.
+    fn flush(&mut self) {
+        self.wheel.flush();
+
+        let rearms = mem::take(&mut *self.rearms.borrow_mut());
+        for (id, envelope, when) in rearms {
+            let offset = self.offset_of(when, self.wheel.ticks);
+            let priority = envelope.priority;
+            self.wheel.schedule(id, envelope, offset, when, priority);
+        }
+    }
+
+    fn handle(&mut self, command: Command<T, K>) {
+        match command {
+            Command::Arrange(id, envelope, when) => self.arrange(id, envelope, when),
+            Command::Replace(id, envelope, when, reply) => {
+                let _ = reply.send(self.replace(id, envelope, when));
+            }
+            Command::ArrangeMany(batch) => {
+                let ticks = self.wheel.ticks;
+                let entities = batch
+                    .into_iter()
+                    .map(|(id, envelope, when)| {
+                        let priority = envelope.priority;
+                        (id, envelope, self.offset_of(when, ticks), when, priority)
+                    })
+                    .collect();
+                self.wheel.schedule_many(entities);
+            }
+            Command::Cancel(id, reply) => {
+                let _ = reply.send(self.cancel(id).is_some());
+            }
+            Command::CancelKey(key, reply) => {
+                let id = self.index.borrow().keys.get(&key).copied();
+                let _ = reply.send(id.and_then(|id| self.cancel(id)).is_some());
+            }
+            Command::CancelTag(tag, reply) => {
+                let ids = self.index.borrow().tagged(&tag);
+                let entities = ids
+                    .into_iter()
+                    .filter_map(|id| match self.cancel(id)?.payload {
+                        Payload::Entity(entity) => Some(entity),
+                        _ => None,
+                    })
+                    .collect();
+                let _ = reply.send(entities);
+            }
+            Command::CountTag(tag, reply) => {
+                let count = self.index.borrow().tags.get(&tag).map_or(0, HashSet::len);
+                let _ = reply.send(count);
+            }
+            Command::ShiftTag(tag, delay, reply) => {
+                let ids = self.index.borrow().tagged(&tag);
+                let shifted = ids
+                    .into_iter()
+                    .filter(|&id| self.reschedule(id, |when| when + delay))
+                    .count();
+                let _ = reply.send(shifted);
+            }
+            Command::Reschedule(id, when, reply) => {
+                let _ = reply.send(self.reschedule(id, |_| when));
+            }
+            Command::Deadline(id, reply) => {
+                let _ = reply.send(self.wheel.get(id).map(|entity| entity.when));
+            }
+            Command::Inspect(inspection) => inspection(&self.wheel),
+            // the run loop exits before handling it
+            Command::Shutdown => {}
+            Command::Ack(id, reply) => {
+                // only a delivered one waits for the ack
+                let delivered = self
+                    .wheel
+                    .get(id)
+                    .is_some_and(|entity| entity.data.attempts > 0);
+                let _ = reply.send(delivered && self.cancel(id).is_some());
+            }
+        }
+    }
+
+    fn arrange(&mut self, id: u64, envelope: Envelope<T, K>, when: SystemTime) {
+        let replaced = self.index.borrow_mut().insert(id, &envelope);
+        if let Some(replaced) = replaced {
+            // the pending task of the key is replaced
+            self.cancel(replaced);
+        }
+
+        let offset = self.offset_of(when, self.wheel.ticks);
+        let priority = envelope.priority;
+        self.wheel.schedule(id, envelope, offset, when, priority);
+    }
+
+    /// Arrange a keyed task in the room the pending task of its key is admitted with.
+    fn replace(
+        &mut self,
+        id: u64,
+        envelope: Envelope<T, K>,
+        when: SystemTime,
+    ) -> Result<(), Envelope<T, K>> {
+        let replaced = envelope
+            .key
+            .as_ref()
+            .and_then(|key| self.index.borrow().keys.get(key).copied());
+        let handed_over = match (replaced.and_then(|id| self.wheel.get(id)), &self.admission) {
+            (Some(pending), Some(admission)) => {
+                admission.replace(&pending.data.tags, &envelope.tags)
+            }
+            _ => false,
+        };
+        if !handed_over {
+            return Err(envelope);
+        }
+
+        // the room is handed over, the replaced one is not released
+        if let Some(replaced) = replaced {
+            if let Some(entity) = self.wheel.cancel(replaced) {
+                self.index.borrow_mut().remove(replaced, &entity.data);
+            }
+        }
+        self.arrange(id, envelope, when);
+        Ok(())
+    }
+
+    fn cancel(&mut self, id: u64) -> Option<Envelope<T, K>> {
+        let envelope = self.wheel.cancel(id)?.data;
+        self.index.borrow_mut().remove(id, &envelope);
+        if let Some(admission) = self
+            .admission
+            .as_ref()
+            .filter(|_| envelope.payload.is_task())
+        {
+            admission.release(&envelope.tags);
+        }
+        Some(envelope)
+    }
+
+    /// Move a pending task from its deadline to `when`, it keeps its id and its place in the index.
+    fn reschedule(&mut self, id: u64, when: impl FnOnce(SystemTime) -> SystemTime) -> bool {
+        match self.wheel.cancel(id) {
+            Some(entity) => {
+                let when = when(entity.when);
+                let offset = self.offset_of(when, self.wheel.ticks);
+                self.wheel
+                    .schedule(id, entity.data, offset, when, entity.priority);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ticks from the current tick to `when`, 0 if it is expired.
+    fn offset_of(&self, when: SystemTime, ticks: u64) -> u64 {
+        self.clock.tick_of(when).saturating_sub(ticks)
+    }
+}
+
+fn run<T: Debug + 'static, K: Hash + Eq + Clone + 'static>(
+    epoch: Epoch,
+    rounding: Rounding,
+    commands: Receiver<Command<T, K>>,
+    router: Router<T>,
+    admission: Option<Arc<Admission>>,
+    reliable: Option<Reliable<T>>,
+) {
+    let Epoch {
+        start_at,
+        start,
+        interval,
+    } = epoch;
+    let interval_in_nanos = interval.as_nanos() as u64;
+
+    let router = Rc::new(router);
+    let outbox = Rc::new(RefCell::new(Outbox::new()));
+    let notice = {
+        let router = router.clone();
+        let outbox = outbox.clone();
+        move |id, envelope| outbox.borrow_mut().push(id, envelope, &router)
+    };
+
+    let clock = Clock::new(start_at, interval, rounding);
+    let mut driver = Driver::new(clock, admission, reliable, notice);
+
+    let mut received = None;
+
+    thread::sleep(interval);
+    loop {
+        let should_ticks = (start.elapsed().as_nanos() / interval_in_nanos as u128) as u64;
+
+        let one_loop_start = Instant::now();
+        // only the commands already sent, a busy producer can not hold off the next tick
+        let pending = commands.len();
+        let sent = received
+            .take()
+            .into_iter()
+            .chain(commands.try_iter().take(pending));
+        if !driver.step(should_ticks, sent) {
+            return;
+        }
+        outbox.borrow_mut().flush(&router);
+
+        let next_ticks = driver.wheel.next_ticks();
+
+        let mut next_tick_time = interval_in_nanos * next_ticks as u64;
+        if !outbox.borrow().is_empty() {
+            // the receivers are full, try again next tick
+            next_tick_time = interval_in_nanos;
+        }
+        let process_time = one_loop_start.elapsed().as_nanos() as u64;
+
+        if next_tick_time > process_time {
+            let timeout = Duration::from_nanos(next_tick_time - process_time);
+            received = match commands.recv_timeout(timeout) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                // the scheduler is dropped, exit once the pending tasks are delivered
+                Err(RecvTimeoutError::Disconnected) => {
+                    if driver.wheel.len() == 0 && outbox.borrow().is_empty() {
+                        return;
+                    }
+                    thread::sleep(timeout);
+                    None
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_wheel::Recurrence;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    type Noticed = Rc<RefCell<Vec<u32>>>;
+
+    /// A driver ticked by hand, with tick 0 an hour ago, so every tick is past its deadline.
+    fn driver(reliable: Option<Reliable<u32>>) -> (Driver<u32, &'static str>, Noticed, SystemTime) {
+        let start_at = SystemTime::now() - Duration::from_secs(3600);
+        let clock = Clock::new(start_at, INTERVAL, Rounding::NeverEarly);
+        let noticed = Noticed::default();
+        let notice = {
+            let noticed = noticed.clone();
+            move |_, envelope: Envelope<u32, &'static str>| {
+                if let Payload::Entity(entity) = envelope.payload {
+                    noticed.borrow_mut().push(entity);
+                }
+            }
+        };
+        (
+            Driver::new(clock, None, reliable, notice),
+            noticed,
+            start_at,
+        )
+    }
+
+    fn envelope(entity: u32, key: Option<&'static str>) -> Envelope<u32, &'static str> {
+        Envelope {
+            payload: Payload::Entity(entity),
+            topic: None,
+            priority: 0,
+            key,
+            tags: Vec::new(),
+            attempts: 0,
+            recurrence: None,
+        }
+    }
+
+    #[test]
+    fn test_rearm_at_deadline() {
+        let (mut driver, noticed, start_at) = driver(None);
+
+        let deadline = start_at + INTERVAL * 10;
+        driver.step(0, [Command::Arrange(1, envelope(1, Some("a")), deadline)]);
+        // re-armed while the deadline tick is due
+        let rearm = Command::Arrange(2, envelope(2, Some("a")), deadline + INTERVAL * 10);
+        driver.step(10, [rearm]);
+        assert!(noticed.borrow().is_empty());
+
+        driver.step(20, []);
+        assert_eq!(*noticed.borrow(), vec![2]);
+        assert_eq!(driver.wheel.len(), 0);
+    }
+
+    #[test]
+    fn test_ack_at_visibility_timeout() {
+        let (dead_letters, dead_receiver) = crossbeam_channel::unbounded();
+        let reliable = Reliable {
+            visibility_timeout: Duration::ZERO,
+            max_redeliveries: 0,
+            cloner: |entity| *entity,
+            dead_letters,
+        };
+        let (mut driver, noticed, start_at) = driver(Some(reliable));
+
+        driver.step(0, [Command::Arrange(1, envelope(1, None), start_at)]);
+        driver.step(1, []);
+        assert_eq!(*noticed.borrow(), vec![1]);
+
+        // acked while the tick of the visibility timeout is due
+        let timeout = driver.clock.tick_of(driver.wheel.get(1).unwrap().when);
+        let (reply, acked) = crossbeam_channel::bounded(1);
+        driver.step(timeout, [Command::Ack(1, reply)]);
+        assert!(acked.recv().unwrap());
+        assert!(dead_receiver.try_recv().is_err());
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(driver.wheel.len(), 0);
+    }
+
+    #[test]
+    fn test_cancel_at_occurrence() {
+        /// A MINUTELY task of key "a" and tag "t" delivered once, with the tick of its next occurrence.
+        fn recurring() -> (Driver<u32, &'static str>, Noticed, u64) {
+            let (mut driver, noticed, start_at) = driver(None);
+            let rule = "FREQ=MINUTELY".parse::<crate::RRule>().unwrap();
+            let mut occurrences = rule.dtstart(start_at).occurrences();
+            let first = occurrences.next().unwrap();
+            let envelope = Envelope {
+                tags: vec!["t".to_string()],
+                recurrence: Some(Box::new(Recurrence {
+                    occurrences,
+                    cloner: |entity| *entity,
+                })),
+                ..envelope(1, Some("a"))
+            };
+
+            driver.step(0, [Command::Arrange(1, envelope, first)]);
+            driver.step(1, []);
+            assert_eq!(*noticed.borrow(), vec![1]);
+            let next = driver.clock.tick_of(driver.wheel.get(1).unwrap().when);
+            (driver, noticed, next)
+        }
+
+        // cancelled while the tick of the next occurrence is due, it does not recur
+        let (mut driver, noticed, next) = recurring();
+        let (reply, cancelled) = crossbeam_channel::bounded(1);
+        driver.step(next, [Command::Cancel(1, reply)]);
+        assert!(cancelled.recv().unwrap());
+        driver.step(next * 3, []);
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(driver.wheel.len(), 0);
+
+        let (mut driver, noticed, next) = recurring();
+        let (reply, cancelled) = crossbeam_channel::bounded(1);
+        driver.step(next, [Command::CancelKey("a", reply)]);
+        assert!(cancelled.recv().unwrap());
+        driver.step(next * 3, []);
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(driver.wheel.len(), 0);
+
+        let (mut driver, noticed, next) = recurring();
+        let (reply, cancelled) = crossbeam_channel::bounded(1);
+        driver.step(next, [Command::CancelTag("t".to_string(), reply)]);
+        assert_eq!(cancelled.recv().unwrap(), vec![1]);
+        driver.step(next * 3, []);
+        assert_eq!(*noticed.borrow(), vec![1]);
+        assert_eq!(driver.wheel.len(), 0);
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Sender;
+
+use crate::{Builder, Scheduler, TickReceiver, TimerId};
+
+/// A change of the heartbeat state of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchdogEvent<Id> {
+    /// No heartbeat for `count` times the timeout of the peer.
+    Missed { id: Id, count: u32 },
+    /// A heartbeat arrived after the peer was reported missed `count` times its timeout.
+    Recovered { id: Id, count: u32 },
+}
+
+struct Peer {
+    timeout: Duration,
+    last_beat: SystemTime,
+    /// The pending timer, `None` past the last threshold.
+    timer: Option<TimerId>,
+    /// Index of the next threshold to report.
+    stage: usize,
+}
+
+struct Peers<Id> {
+    map: HashMap<Id, Peer>,
+    /// Multiples of the timeout to report at, ascending.
+    thresholds: Vec<u32>,
+}
+
+/// Watches the heartbeats of many peers, one timer per peer.
+///
+/// A heartbeat moves the deadline of the timer in the wheel, and a silent peer is reported
+/// `Missed` at each threshold, e.g. a warning at 1x its timeout and dead at 3x.
+///
+/// # Example
+///
+/// ```
+/// use xpd_timer::{Watchdog, WatchdogEvent};
+/// use std::time::Duration;
+///
+/// let (watchdog, events) = Watchdog::new(Duration::from_millis(1));
+/// let watchdog = watchdog.thresholds([1, 3]);
+/// watchdog.register("peer", Duration::from_millis(10));
+///
+/// assert_eq!(events.recv().unwrap(), WatchdogEvent::Missed { id: "peer", count: 1 });
+/// assert_eq!(events.recv().unwrap(), WatchdogEvent::Missed { id: "peer", count: 3 });
+///
+/// watchdog.heartbeat(&"peer");
+/// assert_eq!(events.recv().unwrap(), WatchdogEvent::Recovered { id: "peer", count: 3 });
+/// ```
+pub struct Watchdog<Id> {
+    peers: Arc<Mutex<Peers<Id>>>,
+    scheduler: Arc<Scheduler<Id>>,
+    events: Sender<(TimerId, WatchdogEvent<Id>)>,
+}
+
+impl<Id> Watchdog<Id>
+where
+    Id: Hash + Eq + Clone + Debug + Send + 'static,
+{
+    /// New watchdog with a time wheel of the tick `interval`, return it and the receiver of the events.
+    pub fn new(interval: Duration) -> (Self, TickReceiver<WatchdogEvent<Id>>) {
+        let (scheduler, expirations) = Builder::new(interval).workers(1).build();
+        let scheduler = Arc::new(scheduler);
+        let (events, receiver) = crossbeam_channel::unbounded();
+        let peers = Arc::new(Mutex::new(Peers {
+            map: HashMap::new(),
+            thresholds: vec![1],
+        }));
+
+        let weak = Arc::downgrade(&peers);
+        let (timers, sender) = (scheduler.clone(), events.clone());
+        thread::Builder::new()
+            .name("xpd-timer-watchdog".to_string())
+            .spawn(move || escalate(expirations, weak, timers, sender))
+            .expect("failed to spawn watchdog thread");
+
+        let watchdog = Watchdog {
+            peers,
+            scheduler,
+            events,
+        };
+        (watchdog, TickReceiver::new(receiver, None))
+    }
+
+    /// Report a silent peer at these multiples of its timeout, default is `[1]`.
+    pub fn thresholds(self, thresholds: impl IntoIterator<Item = u32>) -> Self {
+        let mut thresholds = thresholds
+            .into_iter()
+            .filter(|&threshold| threshold > 0)
+            .collect::<Vec<_>>();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        if !thresholds.is_empty() {
+            self.peers.lock().unwrap().thresholds = thresholds;
+        }
+        self
+    }
+
+    /// Watch a peer from now on, registering it again replaces its timeout.
+    pub fn register(&self, id: Id, timeout: Duration) {
+        let mut peers = self.peers.lock().unwrap();
+        let now = SystemTime::now();
+        let when = now + timeout * peers.thresholds[0];
+
+        if let Some(timer) = peers.map.get(&id).and_then(|peer| peer.timer) {
+            self.scheduler.cancel(timer);
+        }
+        let timer = self.scheduler.arrange(id.clone()).at(when);
+        peers.map.insert(
+            id,
+            Peer {
+                timeout,
+                last_beat: now,
+                timer: Some(timer),
+                stage: 0,
+            },
+        );
+    }
+
+    /// A heartbeat of a peer, return `false` if it is not registered.
+    ///
+    /// It emits `Recovered` if the peer has been reported missed.
+    pub fn heartbeat(&self, id: &Id) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let Peers { map, thresholds } = &mut *peers;
+        let peer = match map.get_mut(id) {
+            Some(peer) => peer,
+            None => return false,
+        };
+        let now = SystemTime::now();
+        let when = now + peer.timeout * thresholds[0];
+        peer.last_beat = now;
+
+        // in place, unless it has just expired or it is past the last threshold
+        let timer = match peer.timer {
+            Some(timer) if self.scheduler.reschedule(timer, when) => timer,
+            _ => self.scheduler.arrange(id.clone()).at(when),
+        };
+        peer.timer = Some(timer);
+
+        if peer.stage > 0 {
+            let count = thresholds[peer.stage - 1];
+            peer.stage = 0;
+            let event = WatchdogEvent::Recovered {
+                id: id.clone(),
+                count,
+            };
+            let _ = self.events.send((timer, event));
+        }
+        true
+    }
+
+    /// Stop watching a peer, return `false` if it is not registered.
+    pub fn unregister(&self, id: &Id) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        match peers.map.remove(id) {
+            Some(peer) => {
+                if let Some(timer) = peer.timer {
+                    self.scheduler.cancel(timer);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of the watched peers.
+    pub fn len(&self) -> usize {
+        self.peers.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<Id> Drop for Watchdog<Id> {
+    fn drop(&mut self) {
+        // the thread holds the scheduler too, it exits once the timer thread does
+        self.scheduler.shutdown();
+    }
+}
+
+/// Report the silent peers of the expired timers and arm their next thresholds, until the watchdog is dropped.
+fn escalate<Id: Hash + Eq + Clone + Debug + Send + 'static>(
+    expirations: TickReceiver<Id>,
+    peers: Weak<Mutex<Peers<Id>>>,
+    scheduler: Arc<Scheduler<Id>>,
+    events: Sender<(TimerId, WatchdogEvent<Id>)>,
+) {
+    while let Ok((timer, id)) = expirations.recv_with_id() {
+        let peers = match peers.upgrade() {
+            Some(peers) => peers,
+            None => return,
+        };
+        let mut peers = peers.lock().unwrap();
+        let Peers { map, thresholds } = &mut *peers;
+
+        // the peer may have sent a heartbeat after this timer expired
+        let peer = match map.get_mut(&id) {
+            Some(peer) if peer.timer == Some(timer) => peer,
+            _ => continue,
+        };
+        let count = thresholds[peer.stage];
+        peer.stage += 1;
+        peer.timer = thresholds.get(peer.stage).map(|next| {
+            let when = peer.last_beat + peer.timeout * *next;
+            scheduler.arrange(id.clone()).at(when)
+        });
+        let _ = events.send((timer, WatchdogEvent::Missed { id, count }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat() {
+        let (watchdog, events) = Watchdog::new(Duration::from_millis(1));
+        let watchdog = watchdog.thresholds([3, 1]);
+
+        watchdog.register("alive", Duration::from_millis(30));
+        watchdog.register("silent", Duration::from_millis(20));
+        assert_eq!(watchdog.len(), 2);
+        for _ in 0..6 {
+            thread::sleep(Duration::from_millis(10));
+            assert!(watchdog.heartbeat(&"alive"));
+        }
+        assert!(!watchdog.heartbeat(&"unknown"));
+
+        let missed = |count| WatchdogEvent::Missed {
+            id: "silent",
+            count,
+        };
+        assert_eq!(events.recv().unwrap(), missed(1));
+        assert_eq!(events.recv().unwrap(), missed(3));
+
+        assert!(watchdog.unregister(&"alive"));
+        assert!(watchdog.unregister(&"silent"));
+        assert!(!watchdog.unregister(&"silent"));
+        assert!(watchdog.is_empty());
+        assert_eq!(watchdog.scheduler.pending_count(), 0);
+
+        // the escalating thread stops with the watchdog, dropping its events sender
+        watchdog.register("pending", Duration::from_secs(60));
+        drop(watchdog);
+        assert!(events.recv().is_err());
+    }
+
+    #[test]
+    fn test_recovered() {
+        let (watchdog, events) = Watchdog::new(Duration::from_millis(1));
+        let watchdog = watchdog.thresholds([1, 2]);
+        watchdog.register(7, Duration::from_millis(20));
+
+        assert_eq!(
+            events.recv().unwrap(),
+            WatchdogEvent::Missed { id: 7, count: 1 }
+        );
+        watchdog.heartbeat(&7);
+        assert_eq!(
+            events.recv().unwrap(),
+            WatchdogEvent::Recovered { id: 7, count: 1 }
+        );
+        // the thresholds start over from the heartbeat
+        assert_eq!(
+            events.recv().unwrap(),
+            WatchdogEvent::Missed { id: 7, count: 1 }
+        );
+        assert_eq!(
+            events.recv().unwrap(),
+            WatchdogEvent::Missed { id: 7, count: 2 }
+        );
+    }
+}